@@ -1,64 +1,569 @@
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, SubsecRound, TimeZone, Utc};
+use reqwest::{redirect, StatusCode};
+use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
-use std::io::Write;
-use std::path::PathBuf;
-use std::time::{Duration, SystemTime};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use crate::config::{LeaderboardConfig, Source};
 use crate::parser::Leaderboard;
 
+/// Errors from the Advent of Code API that callers may want to handle differently from generic
+/// network or IO errors
+#[derive(Debug, Clone)]
+pub enum Error {
+    /// The session cookie was rejected. Advent of Code doesn't tell us why, but it's usually
+    /// because it has expired or was copied incorrectly.
+    InvalidSession { year: i32, id: usize },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidSession { year, id } => write!(
+                f,
+                "Advent of Code rejected the session cookie when fetching leaderboard {} ({}). \
+                 It has probably expired, please update it in the configuration file",
+                id, year
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Return a short, non-reversible identifier for a session cookie. It's part of the cache
+/// directory name, so it uses FNV-1a which, unlike the standard library hasher, stays the same
+/// across Rust releases.
+fn session_fingerprint(session: &str) -> u64 {
+    session.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// How many times we try to fetch a leaderboard before giving up
+const MAX_ATTEMPTS: u32 = 4;
+
+/// Give up instead of retrying if Advent of Code asks us to wait longer than this
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How many snapshots to keep per leaderboard. Old snapshots are removed after each refresh.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Retention {
+    /// Keep at most this many snapshots
+    pub max_count: Option<usize>,
+
+    /// Remove snapshots older than this
+    pub max_age: Option<Duration>,
+}
+
+/// A leaderboard as it looked when it was fetched
+#[derive(Debug)]
+pub struct Snapshot {
+    pub fetched_at: DateTime<Utc>,
+    pub leaderboard: Leaderboard,
+}
+
+/// Read leaderboard JSON that was exported from Advent of Code. The path `-` reads from stdin.
+/// Since we can't know when the JSON was fetched, we use the file's modification time.
+pub fn read_snapshot(path: &Path) -> Result<Snapshot> {
+    let (json_str, fetched_at) = if path == Path::new("-") {
+        let mut json_str = String::new();
+        io::stdin().read_to_string(&mut json_str)?;
+        (json_str, Utc::now())
+    } else {
+        let modified = std::fs::metadata(path)?.modified()?;
+        (std::fs::read_to_string(path)?, modified.into())
+    };
+
+    let leaderboard = serde_json::from_str(&json_str)
+        .with_context(|| format!("Invalid leaderboard JSON in {}", path.display()))?;
+    Ok(Snapshot {
+        fetched_at: fetched_at.trunc_subsecs(0),
+        leaderboard,
+    })
+}
+
+/// Take an exclusive advisory lock on the given directory. The lock is released when the
+/// returned file is dropped.
+async fn lock_dir(dir: &Path) -> Result<File> {
+    let f = File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(dir.join("lock"))?;
+
+    // Waiting for the lock blocks the thread, so we do it outside of the async runtime
+    Ok(tokio::task::spawn_blocking(move || f.lock().map(|_| f)).await??)
+}
+
+/// Remove temporary snapshot files that were left behind by a process that stopped halfway
+/// through writing. Must only be called while holding the directory lock.
+fn remove_temporary_files(dir: &Path) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.to_string_lossy().ends_with(".json.tmp") {
+            tracing::warn!("Removing incomplete snapshot {}", path.display());
+            std::fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Create a copy of an error so it can be returned to several callers. Our own errors are kept
+/// as is so callers can still tell them apart.
+fn share_error(error: &anyhow::Error) -> anyhow::Error {
+    match error.downcast_ref::<Error>() {
+        Some(e) => e.clone().into(),
+        None => anyhow!("{:#}", error),
+    }
+}
+
+/// The last failed refresh of a leaderboard
+struct Failure {
+    at: Instant,
+    error: anyhow::Error,
+}
+
+/// Lock that is held while refreshing a leaderboard. It remembers the last failure so callers
+/// that waited for a failed refresh get the same error instead of trying again.
+type Flight = Arc<tokio::sync::Mutex<Option<Failure>>>;
+
 pub struct Client {
     session: String,
     cache_dir: PathBuf,
+    base_url: String,
+    retention: Retention,
+
+    /// One lock per leaderboard and session, so that simultaneous fetches of the same leaderboard
+    /// share one request while different leaderboards can be fetched in parallel
+    flights: Mutex<HashMap<(i32, usize, u64), Flight>>,
 }
 
 impl Client {
-    pub fn new<S: Into<String>, P: Into<PathBuf>>(session: S, cache_dir: P) -> Self {
+    /// Create a new client. The base URL is usually `https://adventofcode.com`, but can be
+    /// pointed to a mock server for local development
+    pub fn new<S, P, U>(session: S, cache_dir: P, base_url: U) -> Self
+    where
+        S: Into<String>,
+        P: Into<PathBuf>,
+        U: Into<String>,
+    {
+        let mut base_url = base_url.into();
+        while base_url.ends_with('/') {
+            base_url.pop();
+        }
+
         Self {
             session: session.into(),
             cache_dir: cache_dir.into(),
+            base_url,
+            retention: Retention::default(),
+            flights: Mutex::new(HashMap::new()),
         }
     }
 
-    pub async fn fetch(&self, year: i32, id: usize) -> Result<Leaderboard> {
-        let cache_path = self
-            .cache_dir
-            .join(format!("aoc-leaderboard-{}-{}.json", year, id));
-
-        // We're only allowed to fetch the JSON once every 15 min. Check if we have a cached
-        // version before trying
-        let use_cached_json = if let Ok(m) = cache_path.as_path().metadata() {
-            let last_modified = SystemTime::now()
-                .duration_since(m.modified()?)
-                .unwrap_or(Duration::ZERO);
-            last_modified < Duration::from_secs(15 * 60)
-        } else {
-            false
+    /// Limit how many snapshots are kept. By default every snapshot is kept forever.
+    pub fn with_retention(mut self, retention: Retention) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Return the directory where snapshots of the given leaderboard are stored. Every
+    /// successful fetch is saved as `<unix timestamp>.json` in this directory.
+    ///
+    /// Different sessions may not be able to see the same leaderboards, so we key the archive
+    /// on the session as well. The session is hashed to not leak it through the file system.
+    fn snapshot_dir(&self, year: i32, id: usize, session: &str) -> PathBuf {
+        self.cache_dir.join(format!(
+            "aoc-leaderboard-{}-{}-{:016x}",
+            year,
+            id,
+            session_fingerprint(session)
+        ))
+    }
+
+    /// List the times of all snapshots we have of the given leaderboard, oldest first
+    pub fn snapshots(
+        &self,
+        year: i32,
+        id: usize,
+        session: Option<&str>,
+    ) -> Result<Vec<DateTime<Utc>>> {
+        let dir = self.snapshot_dir(year, id, session.unwrap_or(&self.session));
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
         };
 
-        let json_str = if use_cached_json {
-            std::fs::read_to_string(cache_path)?
-        } else {
-            // TODO: Detect if session is wrong since it redirects
-            tracing::info!("Refreshing cached leaderboard {} ({})", id, year);
-            let client = reqwest::Client::new();
+        let mut snapshots = Vec::new();
+        for entry in entries {
+            let name = entry?.file_name();
+            let ts = name
+                .to_str()
+                .and_then(|n| n.strip_suffix(".json"))
+                .and_then(|n| n.parse().ok())
+                .and_then(|ts| Utc.timestamp_opt(ts, 0).single());
+
+            // Ignore anything that doesn't look like a snapshot
+            if let Some(ts) = ts {
+                snapshots.push(ts);
+            }
+        }
+        snapshots.sort();
+        Ok(snapshots)
+    }
+
+    /// Load the most recent snapshot taken at or before the given time
+    pub fn load_snapshot(
+        &self,
+        year: i32,
+        id: usize,
+        session: Option<&str>,
+        at: DateTime<Utc>,
+    ) -> Result<Option<Snapshot>> {
+        let fetched_at = match self
+            .snapshots(year, id, session)?
+            .into_iter()
+            .rev()
+            .find(|&ts| ts <= at)
+        {
+            Some(ts) => ts,
+            None => return Ok(None),
+        };
+
+        let path = self
+            .snapshot_dir(year, id, session.unwrap_or(&self.session))
+            .join(format!("{}.json", fetched_at.timestamp()));
+        Ok(Some(Snapshot {
+            fetched_at,
+            leaderboard: serde_json::from_str(&std::fs::read_to_string(path)?)?,
+        }))
+    }
+
+    /// Remove snapshots that fall outside of the retention limits
+    fn prune_snapshots(&self, year: i32, id: usize, session: &str) -> Result<()> {
+        let snapshots = self.snapshots(year, id, Some(session))?;
+        let oldest_by_age = self
+            .retention
+            .max_age
+            .and_then(|age| chrono::Duration::from_std(age).ok())
+            .map(|age| Utc::now() - age);
+        let excess = self
+            .retention
+            .max_count
+            .map(|max_count| snapshots.len().saturating_sub(max_count))
+            .unwrap_or(0);
+
+        // The newest snapshot is always kept, since it's what limits how often we refresh
+        let dir = self.snapshot_dir(year, id, session);
+        let num_prunable = snapshots.len().saturating_sub(1);
+        for (i, ts) in snapshots.into_iter().take(num_prunable).enumerate() {
+            if i < excess || oldest_by_age.is_some_and(|oldest| ts < oldest) {
+                std::fs::remove_file(dir.join(format!("{}.json", ts.timestamp())))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fetch a leaderboard using the given session cookie, or the client's default session if
+    /// none is given
+    ///
+    /// Only one fetch per leaderboard is in flight at a time. Callers that arrive while a fetch
+    /// is running wait for it and then get its result, either from the snapshot it saved or the
+    /// error it ran into.
+    pub async fn fetch(&self, year: i32, id: usize, session: Option<&str>) -> Result<Snapshot> {
+        let session = session.unwrap_or(&self.session);
+        let flight = self
+            .flights
+            .lock()
+            .unwrap()
+            .entry((year, id, session_fingerprint(session)))
+            .or_default()
+            .clone();
+
+        let waiting_since = Instant::now();
+        let mut last_failure = flight.lock().await;
+        if let Some(failure) = last_failure.as_ref() {
+            if failure.at >= waiting_since {
+                return Err(share_error(&failure.error));
+            }
+        }
+
+        let result = self.fetch_exclusive(year, id, session).await;
+        if let Err(e) = &result {
+            *last_failure = Some(Failure {
+                at: Instant::now(),
+                error: share_error(e),
+            });
+        }
+        result
+    }
+
+    /// Fetch a leaderboard like [`Client::fetch`], but if that fails fall back to the most recent
+    /// snapshot. The error is returned alongside the snapshot so the caller can tell that the
+    /// leaderboard may be outdated.
+    pub async fn fetch_with_fallback(
+        &self,
+        year: i32,
+        id: usize,
+        session: Option<&str>,
+    ) -> Result<(Snapshot, Option<anyhow::Error>)> {
+        let error = match self.fetch(year, id, session).await {
+            Ok(snapshot) => return Ok((snapshot, None)),
+            Err(e) => e,
+        };
+
+        match self.load_snapshot(year, id, session, Utc::now())? {
+            Some(snapshot) => Ok((snapshot, Some(error))),
+            None => Err(error),
+        }
+    }
+
+    /// Load the leaderboard for the given configuration from wherever its source points to. Like
+    /// [`Client::fetch_with_fallback`], this may return an outdated leaderboard along with the
+    /// error that prevented us from refreshing it.
+    pub async fn fetch_leaderboard(
+        &self,
+        cfg: &LeaderboardConfig,
+    ) -> Result<(Snapshot, Option<anyhow::Error>)> {
+        let (mut snapshot, mut error) = match &cfg.source {
+            Source::Api => {
+                self.fetch_with_fallback(cfg.year, cfg.id, cfg.session.as_deref())
+                    .await?
+            }
+            Source::File(path) => (read_snapshot(path)?, None),
+        };
+
+        // The combined leaderboard is only as fresh as the oldest one
+        for source in cfg.combine.iter() {
+            let session = source.session.as_deref().or(cfg.session.as_deref());
+            let (other, other_error) = self
+                .fetch_with_fallback(cfg.year, source.id, session)
+                .await?;
+            snapshot.fetched_at = snapshot.fetched_at.min(other.fetched_at);
+            snapshot
+                .leaderboard
+                .combine(cfg.id, other.leaderboard, source.id);
+            error = error.or(other_error);
+        }
+        Ok((snapshot, error))
+    }
+
+    /// Fetch a leaderboard. Must only be called while holding the leaderboard's flight lock
+    async fn fetch_exclusive(&self, year: i32, id: usize, session: &str) -> Result<Snapshot> {
+        // Other processes may share the cache directory, so we hold an advisory lock on the
+        // snapshot directory while refreshing. That way they respect the rate limit together.
+        let dir = self.snapshot_dir(year, id, session);
+        std::fs::create_dir_all(&dir)?;
+        let _lock = lock_dir(&dir).await?;
+        remove_temporary_files(&dir)?;
+
+        // We're only allowed to fetch the JSON once every 15 min. Check if we have a recent
+        // enough snapshot before trying
+        let now = Utc::now();
+        if let Some(snapshot) = self.load_snapshot(year, id, Some(session), now)? {
+            if now - snapshot.fetched_at < chrono::Duration::minutes(15) {
+                return Ok(snapshot);
+            }
+        }
+
+        tracing::info!("Refreshing cached leaderboard {} ({})", id, year);
+        let json_str = self.request(year, id, session).await?;
+        let leaderboard: Leaderboard = match serde_json::from_str(&json_str) {
+            Ok(leaderboard) => leaderboard,
+
+            // If we get an HTML page back we're most likely looking at a login page
+            Err(_) if json_str.trim_start().starts_with('<') => {
+                return Err(Error::InvalidSession { year, id }.into())
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        // Only save content that we know is a proper leaderboard, so an error page never ends
+        // up in the archive. The snapshot is written to a temporary file first and then moved
+        // into place, so a crash never leaves a half written snapshot behind.
+        let path = dir.join(format!("{}.json", now.timestamp()));
+        let tmp_path = dir.join(format!("{}.json.tmp", now.timestamp()));
+        let mut f = File::create(&tmp_path)?;
+        f.write_all(json_str.as_ref())?;
+        f.sync_all()?;
+        std::fs::rename(&tmp_path, &path)?;
+
+        if let Err(e) = self.prune_snapshots(year, id, session) {
+            tracing::warn!(
+                "Unable to prune snapshots of leaderboard {} ({}): {:#}",
+                id,
+                year,
+                e
+            );
+        }
+
+        Ok(Snapshot {
+            fetched_at: now.trunc_subsecs(0),
+            leaderboard,
+        })
+    }
+
+    /// Request the leaderboard JSON from Advent of Code. Requests that fail because the site is
+    /// overloaded or unreachable are retried a few times with an increasing delay.
+    async fn request(&self, year: i32, id: usize, session: &str) -> Result<String> {
+        // Advent of Code redirects to a login page when the session is invalid. We don't follow
+        // redirects so we can tell that apart from an actual leaderboard
+        let client = reqwest::Client::builder()
+            .redirect(redirect::Policy::none())
+            .timeout(REQUEST_TIMEOUT)
+            .build()?;
+
+        let mut attempt = 1;
+        loop {
             let rsp = client
-                .get(&format!(
-                    "https://adventofcode.com/{}/leaderboard/private/view/{}.json",
-                    year, id
+                .get(format!(
+                    "{}/{}/leaderboard/private/view/{}.json",
+                    self.base_url, year, id
                 ))
-                .header("Cookie", &format!("session={}", &self.session))
+                .header("Cookie", &format!("session={}", session))
                 .send()
-                .await?
-                .text()
-                .await?;
+                .await;
+
+            let (error, delay) = match rsp {
+                Ok(rsp) => {
+                    let status = rsp.status();
+                    if status.is_success() {
+                        return Ok(rsp.text().await?);
+                    } else if status.is_redirection()
+                        || status == StatusCode::UNAUTHORIZED
+                        || status == StatusCode::FORBIDDEN
+                    {
+                        return Err(Error::InvalidSession { year, id }.into());
+                    }
+
+                    let error = anyhow!(
+                        "Unexpected response {} when fetching leaderboard {} ({})",
+                        status,
+                        id,
+                        year
+                    );
+                    if status != StatusCode::TOO_MANY_REQUESTS && !status.is_server_error() {
+                        return Err(error);
+                    }
+                    (error, retry_after(&rsp).unwrap_or_else(|| backoff(attempt)))
+                }
+                Err(e) if e.is_connect() || e.is_timeout() => (e.into(), backoff(attempt)),
+                Err(e) => return Err(e.into()),
+            };
+
+            if attempt >= MAX_ATTEMPTS || delay > MAX_RETRY_DELAY {
+                return Err(error);
+            }
+
+            tracing::warn!("{:#}, retrying in {} s", error, delay.as_secs());
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// Return how long to wait before the given attempt is retried
+fn backoff(attempt: u32) -> Duration {
+    Duration::from_secs(2u64.pow(attempt))
+}
+
+/// Return how long the server asked us to wait before retrying, if it told us
+fn retry_after(rsp: &reqwest::Response) -> Option<Duration> {
+    let value = rsp
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?;
+
+    // The header is either a number of seconds or an HTTP date
+    if let Ok(secs) = value.trim().parse() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (at.with_timezone(&Utc) - Utc::now())
+            .to_std()
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            // Save updated content in the cache
-            let mut f = File::create(cache_path)?;
-            f.write_all(rsp.as_ref())?;
+    /// Return a client that keeps its snapshots in a fresh temporary directory
+    fn client(name: &str, retention: Retention) -> Client {
+        let dir = std::env::temp_dir().join(format!(
+            "aoc-leaderboard-test-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        Client::new("session", dir, "http://localhost").with_retention(retention)
+    }
 
-            rsp
+    /// Write snapshots that were taken the given number of days ago
+    fn write_snapshots(client: &Client, days_ago: &[i64]) {
+        let dir = client.snapshot_dir(2022, 1, "session");
+        std::fs::create_dir_all(&dir).unwrap();
+        for &days in days_ago {
+            let ts = Utc::now() - chrono::Duration::days(days);
+            std::fs::write(dir.join(format!("{}.json", ts.timestamp())), "{}").unwrap();
+        }
+    }
+
+    /// Prune the snapshots and return how many days ago the remaining ones were taken
+    fn prune(client: &Client) -> Vec<i64> {
+        client.prune_snapshots(2022, 1, "session").unwrap();
+        let days_ago = client
+            .snapshots(2022, 1, None)
+            .unwrap()
+            .into_iter()
+            .map(|ts| (Utc::now() - ts).num_days())
+            .collect();
+        std::fs::remove_dir_all(&client.cache_dir).unwrap();
+        days_ago
+    }
+
+    #[test]
+    fn prune_keeps_the_most_recent_snapshots() {
+        let retention = Retention {
+            max_count: Some(2),
+            max_age: None,
+        };
+        let client = client("max-count", retention);
+        write_snapshots(&client, &[5, 3, 1, 0]);
+        assert_eq!(prune(&client), vec![1, 0]);
+    }
+
+    #[test]
+    fn prune_removes_old_snapshots() {
+        let retention = Retention {
+            max_count: None,
+            max_age: Some(Duration::from_secs(2 * 24 * 60 * 60)),
         };
+        let client = client("max-age", retention);
+        write_snapshots(&client, &[5, 3, 1, 0]);
+        assert_eq!(prune(&client), vec![1, 0]);
+    }
 
-        Ok(serde_json::from_str(&json_str)?)
+    #[test]
+    fn prune_always_keeps_the_newest_snapshot() {
+        let retention = Retention {
+            max_count: Some(0),
+            max_age: Some(Duration::ZERO),
+        };
+        let client = client("newest", retention);
+        write_snapshots(&client, &[5, 3]);
+        assert_eq!(prune(&client), vec![3]);
     }
 }
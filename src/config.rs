@@ -1,16 +1,28 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, FixedOffset, Utc};
+use chrono_tz::Tz;
 use serde::de::Error;
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::env;
 use std::io::Read;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::api;
+use crate::utils::{next_local_hour, num_days, release_time, MAX_DAYS};
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
+    // Not needed if all leaderboards are read from files
+    #[serde(default)]
     pub session: String,
     #[serde(default = "default_cache_dir")]
     pub cache_dir: PathBuf,
+    #[serde(default = "default_base_url")]
+    pub base_url: String,
+    #[serde(default)]
+    pub snapshots: SnapshotConfig,
     pub leaderboard: Vec<LeaderboardConfig>,
 
     // pub metadata: Option<Vec<Metadata>>,
@@ -18,7 +30,216 @@ pub struct Config {
     pub metadata: HashMap<i32, HashMap<usize, MemberMetadata>>,
 }
 
-#[derive(Debug, Deserialize)]
+/// Limits on how many leaderboard snapshots to keep in the cache directory
+#[derive(Debug, Default, Deserialize)]
+pub struct SnapshotConfig {
+    pub max_count: Option<usize>,
+    pub max_age_days: Option<u64>,
+}
+
+/// Where the JSON for a leaderboard comes from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Source {
+    /// Fetch it from Advent of Code
+    #[default]
+    Api,
+
+    /// Read it from a file, given as `file:<path>`
+    File(PathBuf),
+}
+
+impl<'de> Deserialize<'de> for Source {
+    fn deserialize<D>(de: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let source = String::deserialize(de)?;
+        if source == "api" {
+            Ok(Self::Api)
+        } else if let Some(path) = source.strip_prefix("file:") {
+            Ok(Self::File(path.into()))
+        } else {
+            Err(D::Error::custom(format!(
+                "Invalid source {:?}, expected \"api\" or \"file:<path>\"",
+                source
+            )))
+        }
+    }
+}
+
+/// Timezone of a member, given as a name like `Europe/Stockholm` or a fixed offset like `+05:30`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Timezone {
+    Named(Tz),
+    Fixed(FixedOffset),
+}
+
+impl<'de> Deserialize<'de> for Timezone {
+    fn deserialize<D>(de: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let timezone = String::deserialize(de)?;
+        if timezone.starts_with(['+', '-']) {
+            timezone.parse().map(Self::Fixed).map_err(|_| {
+                D::Error::custom(format!(
+                    "Invalid offset {:?}, expected something like \"+05:30\"",
+                    timezone
+                ))
+            })
+        } else {
+            timezone.parse().map(Self::Named).map_err(D::Error::custom)
+        }
+    }
+}
+
+impl Timezone {
+    /// Return the first time at or after the given time when it's the given hour in this
+    /// timezone
+    pub fn next_hour(&self, after: DateTime<Utc>, hour: u32) -> DateTime<Utc> {
+        match self {
+            Self::Named(tz) => next_local_hour(after, tz, hour),
+            Self::Fixed(offset) => next_local_hour(after, offset, hour),
+        }
+    }
+}
+
+/// How members are scored, see the scoring module for details
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScoringRule {
+    #[default]
+    Consistency,
+    LocalScore,
+    StarsOnly,
+    SolveTime,
+}
+
+/// Points awarded per puzzle by the consistency scoring rule, based on how many whole days after
+/// release it was solved
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScoringCurve {
+    /// Points for solving a puzzle within 24 hours of release
+    pub base: usize,
+
+    /// Points lost for every day late
+    pub decay: usize,
+
+    /// Puzzles solved more than this many days late are worth the floor
+    pub cutoff: usize,
+
+    /// Minimum points for solving a puzzle
+    pub floor: usize,
+
+    /// Points by number of days late, which replaces the parameters above. Puzzles solved later
+    /// than the table covers are worth the last entry.
+    pub table: Option<Vec<usize>>,
+
+    /// Multipliers for the points of the first and second star of each day
+    pub part1_weight: f64,
+    pub part2_weight: f64,
+}
+
+impl Default for ScoringCurve {
+    fn default() -> Self {
+        Self {
+            base: 50,
+            decay: 5,
+            cutoff: 8,
+            floor: 10,
+            table: None,
+            part1_weight: 1.0,
+            part2_weight: 1.0,
+        }
+    }
+}
+
+impl ScoringCurve {
+    /// Return the points for solving a puzzle the given number of whole days after release,
+    /// before weights are applied
+    pub fn points(&self, days_late: usize) -> usize {
+        if let Some(table) = &self.table {
+            return table[days_late.min(table.len() - 1)];
+        }
+
+        if days_late > self.cutoff {
+            self.floor
+        } else {
+            self.base
+                .saturating_sub(self.decay * days_late)
+                .max(self.floor)
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if let Some(table) = &self.table {
+            if table.is_empty() {
+                return Err("scoring table must not be empty".to_owned());
+            }
+        } else if self.floor > self.base {
+            return Err(format!(
+                "scoring floor ({}) must not be greater than the base ({})",
+                self.floor, self.base
+            ));
+        }
+
+        for weight in [self.part1_weight, self.part2_weight] {
+            if !weight.is_finite() || weight < 0.0 {
+                return Err(format!("invalid part weight {}", weight));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How to order members with the same score, see the scoring module for details
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TieBreaker {
+    #[default]
+    Shared,
+    MostStars,
+    EarliestLastStar,
+    SolveTime,
+    LongestStreak,
+}
+
+/// How the scores of team members are combined into a team score, see the scoring module for
+/// details
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TeamAggregation {
+    #[default]
+    Sum,
+    AverageTop(usize),
+    BestPerDay,
+}
+
+/// A group of members that compete together
+#[derive(Debug, Clone, Deserialize)]
+pub struct TeamConfig {
+    pub name: String,
+    pub members: Vec<usize>,
+}
+
+/// Another private leaderboard whose members are shown together with the configured one
+#[derive(Debug, Clone, Deserialize)]
+pub struct CombinedSource {
+    pub id: usize,
+
+    /// Must be the same as the year of the leaderboard it's combined with
+    pub year: Option<i32>,
+
+    /// Session cookie to use for this leaderboard instead of the one for the combined leaderboard
+    #[serde(default)]
+    pub session: Option<String>,
+
+    /// Short name shown next to members of this leaderboard
+    pub label: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct LeaderboardConfig {
     pub id: usize,
     pub name: String,
@@ -26,22 +247,115 @@ pub struct LeaderboardConfig {
     pub code: String,
     pub year: i32, // We use i32 since that's what chrono expects
 
+    /// Session cookie to use for this leaderboard instead of the global one
     #[serde(default)]
+    pub session: Option<String>,
+
+    /// Repositories by member ID, used for members that don't have one in the metadata
+    #[serde(default, deserialize_with = "parse_repositories")]
     pub repositories: HashMap<usize, String>,
 
     #[serde(default)]
-    pub header: String,
-}
+    pub source: Source,
+
+    /// Number of puzzles, if it differs from what we expect for the year
+    #[serde(default)]
+    pub days: Option<u32>,
+
+    #[serde(default)]
+    pub scoring: ScoringRule,
+
+    /// Points for the consistency scoring rule
+    #[serde(default)]
+    pub scoring_curve: ScoringCurve,
+
+    #[serde(default)]
+    pub tie_breaker: TieBreaker,
+
+    /// Days that don't count towards the score
+    #[serde(default)]
+    pub voided_days: Vec<usize>,
 
-#[derive(Debug)]
-pub struct Metadata {
-    pub year: i32,
-    pub members: HashMap<usize, MemberMetadata>,
+    /// Multiply the points for every star on these days
+    #[serde(default, deserialize_with = "parse_day_multipliers")]
+    pub day_multipliers: HashMap<usize, f64>,
+
+    /// Score members relative to the first time it's this hour in their own timezone after the
+    /// puzzle is released, instead of the actual release time
+    #[serde(default)]
+    pub local_release_hour: Option<u32>,
+
+    /// Extra points for each day in the longest streak of puzzles solved within 24 hours
+    #[serde(default)]
+    pub streak_bonus: usize,
+
+    /// Hide members with fewer stars than this
+    #[serde(default)]
+    pub min_stars: usize,
+
+    /// Hide members that haven't gotten a star in this many days
+    #[serde(default)]
+    pub hide_inactive_days: Option<u32>,
+
+    /// Other private leaderboards to show together with this one
+    #[serde(default)]
+    pub combine: Vec<CombinedSource>,
+
+    /// Short name shown next to members of this leaderboard when it's combined with others
+    pub label: Option<String>,
+
+    #[serde(default)]
+    pub teams: Vec<TeamConfig>,
+
+    #[serde(default)]
+    pub team_aggregation: TeamAggregation,
+
+    #[serde(default)]
+    pub header: String,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct MemberMetadata {
     pub repository: Option<String>,
+    pub timezone: Option<Timezone>,
+
+    /// Other accounts of this member, whose stars are merged into this one
+    #[serde(default)]
+    pub aliases: Vec<usize>,
+
+    /// Don't show this member on the leaderboard
+    #[serde(default)]
+    pub hidden: bool,
+}
+
+fn parse_repositories<'de, D>(de: D) -> Result<HashMap<usize, String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    // TOML keys are always strings, so we have to parse the member IDs ourselves
+    let raw: HashMap<String, String> = Deserialize::deserialize(de)?;
+    raw.into_iter()
+        .map(|(member_id, repository)| {
+            member_id.parse().map(|id| (id, repository)).map_err(|_| {
+                D::Error::custom(format!("Member ID must be an integer (got {})", member_id))
+            })
+        })
+        .collect()
+}
+
+fn parse_day_multipliers<'de, D>(de: D) -> Result<HashMap<usize, f64>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    // TOML keys are always strings, so we have to parse the days ourselves
+    let raw: HashMap<String, f64> = Deserialize::deserialize(de)?;
+    raw.into_iter()
+        .map(|(day, multiplier)| {
+            day.parse()
+                .map(|day| (day, multiplier))
+                .map_err(|_| D::Error::custom(format!("Day must be an integer (got {})", day)))
+        })
+        .collect()
 }
 
 fn parse_metadata<'de, D>(de: D) -> Result<HashMap<i32, HashMap<usize, MemberMetadata>>, D::Error>
@@ -92,10 +406,226 @@ fn default_cache_dir() -> PathBuf {
         .unwrap_or_else(env::temp_dir)
 }
 
+fn default_base_url() -> String {
+    "https://adventofcode.com".to_owned()
+}
+
 impl Config {
+    /// Create an API client using the global settings
+    pub fn client(&self) -> api::Client {
+        api::Client::new(&self.session, &self.cache_dir, &self.base_url).with_retention(
+            api::Retention {
+                max_count: self.snapshots.max_count,
+                max_age: self
+                    .snapshots
+                    .max_age_days
+                    .map(|days| Duration::from_secs(days * 24 * 60 * 60)),
+            },
+        )
+    }
+
     pub fn from_file<P: AsRef<Path>>(p: P) -> Result<Config> {
         let mut config_str = String::new();
         std::fs::File::open(p)?.read_to_string(&mut config_str)?;
-        Ok(toml::from_str(&config_str)?)
+        let config: Config = toml::from_str(&config_str)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check for errors that can't be expressed through the types alone
+    fn validate(&self) -> Result<()> {
+        for (year, members) in self.metadata.iter() {
+            let mut aliases = HashMap::new();
+            for (&member_id, m) in members.iter() {
+                for &alias in m.aliases.iter() {
+                    // This also covers members that list themselves as an alias
+                    if members.get(&alias).is_some_and(|a| !a.aliases.is_empty()) {
+                        return Err(anyhow!(
+                            "Member {} can't be an alias of {} in {}, since it has aliases itself",
+                            alias,
+                            member_id,
+                            year
+                        ));
+                    }
+                    if let Some(other) = aliases.insert(alias, member_id) {
+                        return Err(anyhow!(
+                            "Member {} can't be an alias of both {} and {} in {}",
+                            alias,
+                            other,
+                            member_id,
+                            year
+                        ));
+                    }
+                }
+            }
+        }
+
+        for l in self.leaderboard.iter() {
+            l.scoring_curve.validate().map_err(|e| {
+                anyhow!("Leaderboard {} has an invalid scoring curve: {}", l.slug, e)
+            })?;
+
+            if l.streak_bonus > 0 && l.scoring == ScoringRule::SolveTime {
+                return Err(anyhow!(
+                    "Leaderboard {} can't use streak_bonus with solve_time scoring",
+                    l.slug
+                ));
+            }
+
+            if !l.day_multipliers.is_empty() && l.scoring == ScoringRule::SolveTime {
+                return Err(anyhow!(
+                    "Leaderboard {} can't use day_multipliers with solve_time scoring",
+                    l.slug
+                ));
+            }
+
+            if let Some(days) = l.days {
+                if days == 0 || days > MAX_DAYS {
+                    return Err(anyhow!(
+                        "Leaderboard {} must have between 1 and {} days (got {})",
+                        l.slug,
+                        MAX_DAYS,
+                        days
+                    ));
+                }
+            }
+
+            if let Some(hour) = l.local_release_hour {
+                if hour > 23 {
+                    return Err(anyhow!(
+                        "Leaderboard {} must have a local_release_hour between 0 and 23 (got {})",
+                        l.slug,
+                        hour
+                    ));
+                }
+            }
+
+            let days = l.num_days();
+            for &day in l.voided_days.iter().chain(l.day_multipliers.keys()) {
+                if day == 0 || day > days {
+                    return Err(anyhow!(
+                        "Leaderboard {} only has days 1 to {} (got {})",
+                        l.slug,
+                        days,
+                        day
+                    ));
+                }
+            }
+            if let Some((day, multiplier)) = l
+                .day_multipliers
+                .iter()
+                .find(|(_, &m)| !m.is_finite() || m < 0.0)
+            {
+                return Err(anyhow!(
+                    "Leaderboard {} has an invalid multiplier for day {} ({})",
+                    l.slug,
+                    day,
+                    multiplier
+                ));
+            }
+
+            let mut board_ids = vec![l.id];
+            for source in l.combine.iter() {
+                if source.year.is_some_and(|year| year != l.year) {
+                    return Err(anyhow!(
+                        "Leaderboard {} can only be combined with leaderboards from {}",
+                        l.slug,
+                        l.year
+                    ));
+                }
+                if board_ids.contains(&source.id) {
+                    return Err(anyhow!(
+                        "Leaderboard {} includes leaderboard {} more than once",
+                        l.slug,
+                        source.id
+                    ));
+                }
+                board_ids.push(source.id);
+            }
+
+            if !l.teams.is_empty() && l.scoring == ScoringRule::SolveTime {
+                return Err(anyhow!(
+                    "Leaderboard {} can't use teams with solve_time scoring",
+                    l.slug
+                ));
+            }
+            if l.team_aggregation == TeamAggregation::AverageTop(0) {
+                return Err(anyhow!(
+                    "Leaderboard {} must average at least one member per team",
+                    l.slug
+                ));
+            }
+            let mut team_members = HashMap::new();
+            for team in l.teams.iter() {
+                for member_id in team.members.iter() {
+                    if let Some(other) = team_members.insert(member_id, &team.name) {
+                        return Err(anyhow!(
+                            "Leaderboard {} has member {} in both {} and {}",
+                            l.slug,
+                            member_id,
+                            other,
+                            team.name
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl LeaderboardConfig {
+    /// Return the number of puzzles on this leaderboard
+    pub fn num_days(&self) -> usize {
+        self.days.unwrap_or_else(|| num_days(self.year)) as usize
+    }
+
+    /// Return when the puzzle for the given day counts as released for a member
+    pub fn release_time(&self, day: usize, member: Option<&MemberMetadata>) -> DateTime<Utc> {
+        let released = release_time(self.year, day as u32).unwrap();
+        match (self.local_release_hour, member.and_then(|m| m.timezone)) {
+            (Some(hour), Some(timezone)) => timezone.next_hour(released, hour),
+            _ => released,
+        }
+    }
+
+    /// Return the label for one of the leaderboards that are combined into this one
+    pub fn board_label(&self, id: usize) -> String {
+        let label = if id == self.id {
+            self.label.as_ref()
+        } else {
+            self.combine
+                .iter()
+                .find(|s| s.id == id)
+                .and_then(|s| s.label.as_ref())
+        };
+        label.cloned().unwrap_or_else(|| format!("#{}", id))
+    }
+
+    /// Return true if the given day doesn't count towards the score
+    pub fn is_voided(&self, day: usize) -> bool {
+        self.voided_days.contains(&day)
+    }
+
+    /// Return how much the points for the given day are multiplied by
+    pub fn day_multiplier(&self, day: usize) -> f64 {
+        if self.is_voided(day) {
+            0.0
+        } else {
+            self.day_multipliers.get(&day).copied().unwrap_or(1.0)
+        }
+    }
+}
+
+impl LeaderboardConfig {
+    /// Return the repository of a member, preferring the one in the metadata
+    pub fn repository<'a>(
+        &'a self,
+        member_id: usize,
+        metadata: Option<&'a MemberMetadata>,
+    ) -> Option<&'a str> {
+        metadata
+            .and_then(|m| m.repository.as_deref())
+            .or_else(|| self.repositories.get(&member_id).map(String::as_str))
     }
 }
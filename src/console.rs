@@ -1,68 +1,222 @@
+use chrono::{DateTime, Utc};
 use std::collections::HashMap;
 
 use crate::config::{LeaderboardConfig, MemberMetadata};
-use crate::model::{Scoreboard, Stars};
+use crate::model::{AllTimeScore, Contention, Scoreboard, Stars};
+use crate::scoring;
+use crate::stats::DayStats;
+use crate::utils;
 
 pub fn render_template(
     cfg: &LeaderboardConfig,
     metadata: &HashMap<usize, MemberMetadata>,
     scoreboard: &Scoreboard,
+    stale_since: Option<DateTime<Utc>>,
 ) {
-    println!("{} ({})", cfg.name, cfg.year);
+    println!("{} ({})", cfg.name, scoreboard.year);
+    let scoring = scoring::from_config(cfg);
+    println!(
+        "{}",
+        utils::wrap(
+            &format!(
+                "Scoring: {}. {} {}",
+                scoring.name(),
+                scoring::description(cfg, scoring.as_ref()),
+                cfg.tie_breaker.description()
+            ),
+            80
+        )
+    );
+    if let Some(updated_at) = stale_since {
+        println!(
+            "\x1b[0;91mUnable to refresh the leaderboard, data last updated at {}\x1b[0m",
+            updated_at.format("%Y-%m-%d %H:%M UTC")
+        );
+    }
+    if let Some(as_of) = scoreboard.as_of {
+        println!(
+            "\x1b[0;96mShowing the standings as of {}\x1b[0m",
+            as_of.format("%Y-%m-%d %H:%M:%S UTC")
+        );
+    }
     println!();
 
-    // Print dates in header row
-    let padding = 4;
-    for i in 1..=25 + padding {
-        if i < 14 {
+    // Print dates in header row, with tens on the first line and ones on the second
+    let padding = "    ";
+    print!("{}", padding);
+    for day in 1..=scoreboard.days {
+        if day < 10 {
             print!(" ");
-        } else if i + padding < 20 {
-            print!("1");
+        } else if scoreboard.voided[day - 1] {
+            print!("\x1b[0;91m{}\x1b[0m", day / 10);
         } else {
-            print!("2");
+            print!("{}", day / 10);
         }
     }
     println!();
-    for i in 1..=25 + padding {
-        if i <= padding {
-            print!(" ");
+    print!("{}", padding);
+    for day in 1..=scoreboard.days {
+        if scoreboard.voided[day - 1] {
+            print!("\x1b[0;91m{}\x1b[0m", day % 10);
         } else {
-            print!("{}", (i - padding) % 10);
+            print!("{}", day % 10);
         }
     }
     println!();
 
     // Print leaderboard
-    let mut last_score = 0;
-    let mut curr_place = 0;
-    for (i, member) in scoreboard.scores.iter().enumerate() {
-        // Check if participant is tied with previous participant. If so reuse place
-        if member.score != last_score {
-            curr_place = i + 1;
+    for member in scoreboard.scores.iter() {
+        print!("{:>2}. ", member.place);
+        for (day, &voided) in member.stars.iter().zip(scoreboard.voided.iter()) {
+            // Voided days are dimmed and crossed out
+            let style = if voided { "2;9" } else { "0" };
+            let color = match day {
+                Stars::Both => 93,
+                Stars::First => 96,
+                Stars::None => 90,
+            };
+            print!("\x1b[{};{}m*\x1b[0m", style, color);
         }
-        last_score = member.score;
-
-        print!("{:>2}. ", curr_place);
-        for day in member.stars {
+        print!(" {:>4}", scoring.format_score(member.score));
+        print!(" \x1b[0;90m{:>4}\x1b[0m", member.local_score);
+        print!(
+            " {:>5}",
+            format!("{}/{}", member.current_streak, member.longest_streak)
+        );
+        if let Some(outlook) = member.outlook {
+            let color = match outlook.contention {
+                Contention::Clinched => 93,
+                Contention::CanWin => 96,
+                Contention::CanFinishTop3 => 0,
+                Contention::Eliminated => 90,
+            };
             print!(
-                "{}",
-                match day {
-                    Stars::Both => "\x1b[0;93m*\x1b[0m",
-                    Stars::First => "\x1b[0;96m*\x1b[0m",
-                    Stars::None => "\x1b[0;90m*\x1b[0m",
-                }
+                " \x1b[0;90m{:>4}\x1b[0m \x1b[0;{}m{}\x1b[0m",
+                scoring.format_score(outlook.best_score),
+                color,
+                outlook.contention.marker()
             );
         }
-        print!(" {:>4}", member.score);
         print!(" {}", member.member.name);
+        if scoreboard.owner_id == Some(member.member.id) {
+            print!(" \x1b[0;96m(owner)\x1b[0m");
+        }
+        if !member.boards.is_empty() {
+            let boards: Vec<_> = member
+                .boards
+                .iter()
+                .map(|&id| cfg.board_label(id))
+                .collect();
+            print!(" \x1b[0;90m[{}]\x1b[0m", boards.join(", "));
+        }
 
-        if let Some(MemberMetadata {
-            repository: Some(repo),
-        }) = metadata.get(&member.member.id)
-        {
+        if let Some(repo) = cfg.repository(member.member.id, metadata.get(&member.member.id)) {
             print!(" ({})", repo);
         }
 
         println!();
     }
+    if scoreboard.scores.iter().any(|m| m.outlook.is_some()) {
+        println!(
+            "{}",
+            utils::wrap(
+                "The last number is the best score the member can still reach. C means they \
+                 have clinched first place, W that they can still win and 3 that they can still \
+                 finish in the top 3.",
+                80
+            )
+        );
+    }
+    if scoreboard.num_hidden > 0 {
+        println!(
+            "\x1b[0;90m{} hidden, use --all to show everyone\x1b[0m",
+            match scoreboard.num_hidden {
+                1 => "1 member is".to_owned(),
+                n => format!("{} members are", n),
+            }
+        );
+    }
+
+    if !scoreboard.teams.is_empty() {
+        println!();
+        println!("Teams");
+        for team in scoreboard.teams.iter() {
+            let members: Vec<_> = team.members.iter().map(|m| m.name.as_str()).collect();
+            println!(
+                "{:>2}. {:>4} {} \x1b[0;90m({})\x1b[0m",
+                team.place,
+                scoring.format_score(team.score),
+                team.name,
+                members.join(", ")
+            );
+        }
+        println!("{}", utils::wrap(&cfg.team_aggregation.description(), 80));
+    }
+}
+
+pub fn render_stats(scoreboard: &Scoreboard, stats: &[DayStats]) {
+    println!(
+        "Day  \x1b[0;96m1st\x1b[0m  \x1b[0;93m2nd\x1b[0m  Fastest 1st   Median 1st  Fastest 2nd   \
+         Median 2nd  Fastest gap   Median gap  First solvers"
+    );
+    let name = |member_id: Option<usize>| {
+        member_id
+            .and_then(|id| scoreboard.member_name(id))
+            .unwrap_or("")
+    };
+    for day in stats {
+        print!(
+            "{:>3}  \x1b[0;96m{:>3}\x1b[0m  \x1b[0;93m{:>3}\x1b[0m",
+            day.day, day.part1.solvers, day.part2.solvers
+        );
+        for duration in [
+            day.part1.fastest,
+            day.part1.median,
+            day.part2.fastest,
+            day.part2.median,
+            day.fastest_gap,
+            day.median_gap,
+        ] {
+            print!(" {:>12}", utils::format_optional_duration(duration));
+        }
+        if day.part1.first_solver.is_some() {
+            print!("  \x1b[0;96m{}\x1b[0m", name(day.part1.first_solver));
+        }
+        if day.part2.first_solver.is_some() {
+            print!(" / \x1b[0;93m{}\x1b[0m", name(day.part2.first_solver));
+        }
+        println!();
+    }
+}
+
+pub fn render_all_time(cfg: &LeaderboardConfig, years: &[i32], scores: &[AllTimeScore]) {
+    println!("{} (all-time)", cfg.name);
+    println!(
+        "{}",
+        utils::wrap(
+            &format!(
+                "Scores and stars are added up across {}, using the scoring rule of each year.",
+                utils::join_years(years)
+            ),
+            80
+        )
+    );
+    println!();
+
+    println!("    Score \x1b[0;93mStars\x1b[0m Years  Best finish");
+    for member in scores {
+        let best_finish = member
+            .best_finish
+            .map(|(place, year)| format!("{} in {}", place, year))
+            .unwrap_or_default();
+        println!(
+            "{:>2}. {:>5} \x1b[0;93m{:>5}\x1b[0m {:>5}  {:>11} {}",
+            member.place,
+            member.score,
+            member.num_stars,
+            member.years.len(),
+            best_finish,
+            member.member.name
+        );
+    }
 }
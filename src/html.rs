@@ -1,20 +1,110 @@
+use chrono::{DateTime, SecondsFormat, Utc};
 use minijinja::{context, Environment, State};
 use serde::Serialize;
 use std::collections::HashMap;
 
+use crate::api;
 use crate::config::{LeaderboardConfig, MemberMetadata};
-use crate::model::{Scoreboard, Stars};
-use crate::utils::release_time;
+use crate::model::{AllTimeScore, Contention, Scoreboard, Stars};
+use crate::scoring;
+use crate::stats::DayStats;
+use crate::utils::{self, release_time};
 
 const TEMPLATE: &str = include_str!("leaderboard.html");
+const STATS_TEMPLATE: &str = include_str!("stats.html");
+const ALL_TIME_TEMPLATE: &str = include_str!("all_time.html");
+const STYLE: &str = include_str!("style.html");
 
 #[derive(Debug, Serialize)]
 struct LeaderboardLine<'a> {
     place: usize,
-    score: usize,
-    star_classes: [&'a str; 25],
+    score: String,
+    local_score: usize,
+    streak: String,
+    outlook: Option<OutlookCell>,
+    star_classes: Vec<&'a str>,
     name: &'a str,
+    summary: String,
     repository: &'a str,
+    is_owner: bool,
+
+    /// Labels of the leaderboards the member is on, if several are combined
+    boards: String,
+}
+
+/// Best score a member can still reach and where they can still finish
+#[derive(Debug, Serialize)]
+struct OutlookCell {
+    best_score: String,
+    marker: &'static str,
+    class: &'static str,
+    title: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+struct TeamLine<'a> {
+    place: usize,
+    score: String,
+    name: &'a str,
+    members: String,
+}
+
+#[derive(Debug, Serialize)]
+struct AllTimeLine<'a> {
+    place: usize,
+    score: usize,
+    num_stars: usize,
+    years: usize,
+    participated: String,
+    best_finish: String,
+    name: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct StatsLine<'a> {
+    day: usize,
+    part1_solvers: usize,
+    part2_solvers: usize,
+    part1_first: &'a str,
+    part2_first: &'a str,
+    part1_fastest: String,
+    part1_median: String,
+    part2_fastest: String,
+    part2_median: String,
+    fastest_gap: String,
+    median_gap: String,
+}
+
+/// Return true if the error means that Advent of Code rejected the session cookie
+fn is_invalid_session(error: Option<&anyhow::Error>) -> bool {
+    error.is_some_and(|e| {
+        matches!(
+            e.downcast_ref::<api::Error>(),
+            Some(api::Error::InvalidSession { .. })
+        )
+    })
+}
+
+/// Describe who solved each day first, using the exact order the stars were awarded in
+fn first_solvers(scoreboard: &Scoreboard) -> Vec<String> {
+    (1..=scoreboard.days)
+        .map(|day| {
+            let first = |part| {
+                scoreboard
+                    .solves
+                    .iter()
+                    .find(|s| s.day == day && s.part == part)
+                    .and_then(|s| scoreboard.member_name(s.member_id))
+            };
+            match (first(1), first(2)) {
+                (Some(first), Some(both)) => {
+                    format!("First star by {}, both stars by {}", first, both)
+                }
+                (Some(first), None) => format!("First star by {}", first),
+                _ => String::new(),
+            }
+        })
+        .collect()
 }
 
 fn chars(_state: &State, value: String) -> Result<Vec<char>, minijinja::Error> {
@@ -28,64 +118,207 @@ fn is_unlocked(_state: &State, year: i32, day: u32) -> Result<bool, minijinja::E
 }
 
 fn left_pad(_state: &State, value: String, width: usize) -> Result<String, minijinja::Error> {
-    Ok(std::iter::repeat(' ')
-        .take(width.saturating_sub(value.len()))
+    Ok(std::iter::repeat_n(' ', width.saturating_sub(value.len()))
         .chain(value.chars())
         .collect())
 }
 
-pub fn render_template(
-    cfg: &LeaderboardConfig,
-    metadata: &HashMap<usize, MemberMetadata>,
-    scoreboard: &Scoreboard,
-) -> String {
+fn wrap(_state: &State, value: String, width: usize) -> Result<String, minijinja::Error> {
+    Ok(utils::wrap(&value, width))
+}
+
+fn environment() -> Environment<'static> {
     let mut env = Environment::new();
     env.add_filter("chars", chars);
     env.add_filter("left_pad", left_pad);
+    env.add_filter("wrap", wrap);
     env.add_function("is_unlocked", is_unlocked);
+    env.add_template("style.html", STYLE).unwrap();
     env.add_template("template", TEMPLATE).unwrap();
+    env.add_template("stats", STATS_TEMPLATE).unwrap();
+    env.add_template("all_time", ALL_TIME_TEMPLATE).unwrap();
+    env
+}
+
+pub fn render_template(
+    cfg: &LeaderboardConfig,
+    metadata: &HashMap<usize, MemberMetadata>,
+    scoreboard: &Scoreboard,
+    stale_since: Option<DateTime<Utc>>,
+    error: Option<&anyhow::Error>,
+) -> String {
+    let env = environment();
     let tmpl = env.get_template("template").unwrap();
 
-    let mut last_score = 0;
-    let mut curr_place = 0;
+    let scoring = scoring::from_config(cfg);
     let mut leaderboard = Vec::new();
-    for (i, member) in scoreboard.scores.iter().enumerate() {
-        // Check if participant is tied with previous participant. If so reuse place
-        if member.score != last_score {
-            curr_place = i + 1;
+    for member in scoreboard.scores.iter() {
+        let repository = cfg
+            .repository(member.member.id, metadata.get(&member.member.id))
+            .unwrap_or("");
+        let mut summary = format!("{} stars", member.num_stars);
+        if let Some(ts) = member.last_star_ts {
+            summary += &format!(", last star at {}", ts.format("%Y-%m-%d %H:%M UTC"));
+        }
+        if member.global_score > 0 {
+            summary += &format!(", {} global points", member.global_score);
         }
-        last_score = member.score;
 
-        let repository = metadata
-            .get(&member.member.id)
-            .and_then(|m| m.repository.as_deref())
-            .unwrap_or("");
         leaderboard.push(LeaderboardLine {
-            place: curr_place,
+            place: member.place,
             star_classes: member
                 .stars
                 .iter()
-                .map(|s| match s {
-                    Stars::None => "star-none",
-                    Stars::First => "star-first-only",
-                    Stars::Both => "star-both",
+                .zip(scoreboard.voided.iter())
+                .map(|(s, &voided)| match (s, voided) {
+                    (Stars::None, false) => "star-none",
+                    (Stars::First, false) => "star-first-only",
+                    (Stars::Both, false) => "star-both",
+                    (Stars::None, true) => "star-none voided",
+                    (Stars::First, true) => "star-first-only voided",
+                    (Stars::Both, true) => "star-both voided",
                 })
-                .collect::<Vec<_>>()
-                .try_into()
-                .unwrap(),
-            score: member.score,
+                .collect(),
+            score: scoring.format_score(member.score),
+            local_score: member.local_score,
+            streak: format!("{}/{}", member.current_streak, member.longest_streak),
+            outlook: member.outlook.map(|o| OutlookCell {
+                best_score: scoring.format_score(o.best_score),
+                marker: o.contention.marker(),
+                class: match o.contention {
+                    Contention::Clinched => "star-both",
+                    Contention::CanWin => "star-first-only",
+                    Contention::CanFinishTop3 => "",
+                    Contention::Eliminated => "local-score",
+                },
+                title: o.contention.description(),
+            }),
             name: &member.member.name,
+            summary,
             repository,
+            is_owner: scoreboard.owner_id == Some(member.member.id),
+            boards: member
+                .boards
+                .iter()
+                .map(|&id| cfg.board_label(id))
+                .collect::<Vec<_>>()
+                .join(", "),
         });
     }
 
+    let teams: Vec<_> = scoreboard
+        .teams
+        .iter()
+        .map(|team| TeamLine {
+            place: team.place,
+            score: scoring.format_score(team.score),
+            name: &team.name,
+            members: team
+                .members
+                .iter()
+                .map(|m| m.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+        })
+        .collect();
+
     tmpl.render(context!(
         leaderboard_id => cfg.id,
+        slug => cfg.slug,
         name => cfg.name,
-        year => cfg.year,
+        year => scoreboard.year,
+        days => scoreboard.days,
+        voided => scoreboard.voided,
         header => cfg.header,
         code => cfg.code,
         leaderboard => leaderboard,
+        teams => teams,
+        team_description => cfg.team_aggregation.description(),
+        first_solvers => first_solvers(scoreboard),
+        scoring_name => scoring.name(),
+        scoring_description => scoring::description(cfg, scoring.as_ref()),
+        tie_breaker_description => cfg.tie_breaker.description(),
+        stale_since => stale_since.map(|t| t.format("%Y-%m-%d %H:%M UTC").to_string()),
+        invalid_session => is_invalid_session(error),
+        as_of => scoreboard.as_of.map(|t| t.format("%Y-%m-%d %H:%M:%S UTC").to_string()),
+        as_of_query => scoreboard.as_of.map(|t| t.to_rfc3339_opts(SecondsFormat::Secs, true)),
+        num_hidden => scoreboard.num_hidden,
+        in_progress => scoreboard.scores.iter().any(|m| m.outlook.is_some()),
+    ))
+    .unwrap()
+}
+
+pub fn render_stats(
+    cfg: &LeaderboardConfig,
+    scoreboard: &Scoreboard,
+    stats: &[DayStats],
+    stale_since: Option<DateTime<Utc>>,
+    error: Option<&anyhow::Error>,
+) -> String {
+    let env = environment();
+    let tmpl = env.get_template("stats").unwrap();
+
+    let name = |member_id: Option<usize>| {
+        member_id
+            .and_then(|id| scoreboard.member_name(id))
+            .unwrap_or("")
+    };
+    let stats: Vec<_> = stats
+        .iter()
+        .map(|day| StatsLine {
+            day: day.day,
+            part1_solvers: day.part1.solvers,
+            part2_solvers: day.part2.solvers,
+            part1_first: name(day.part1.first_solver),
+            part2_first: name(day.part2.first_solver),
+            part1_fastest: utils::format_optional_duration(day.part1.fastest),
+            part1_median: utils::format_optional_duration(day.part1.median),
+            part2_fastest: utils::format_optional_duration(day.part2.fastest),
+            part2_median: utils::format_optional_duration(day.part2.median),
+            fastest_gap: utils::format_optional_duration(day.fastest_gap),
+            median_gap: utils::format_optional_duration(day.median_gap),
+        })
+        .collect();
+
+    tmpl.render(context!(
+        name => cfg.name,
+        slug => cfg.slug,
+        year => scoreboard.year,
+        stats => stats,
+        stale_since => stale_since.map(|t| t.format("%Y-%m-%d %H:%M UTC").to_string()),
+        invalid_session => is_invalid_session(error),
+        as_of => scoreboard.as_of.map(|t| t.format("%Y-%m-%d %H:%M:%S UTC").to_string()),
+    ))
+    .unwrap()
+}
+
+/// Render all-time standings for the leaderboard with the given configuration, which should be
+/// the most recent year
+pub fn render_all_time(cfg: &LeaderboardConfig, years: &[i32], scores: &[AllTimeScore]) -> String {
+    let env = environment();
+    let tmpl = env.get_template("all_time").unwrap();
+
+    let leaderboard: Vec<_> = scores
+        .iter()
+        .map(|m| AllTimeLine {
+            place: m.place,
+            score: m.score,
+            num_stars: m.num_stars,
+            years: m.years.len(),
+            participated: utils::join_years(&m.years),
+            best_finish: m
+                .best_finish
+                .map(|(place, year)| format!("{} in {}", place, year))
+                .unwrap_or_default(),
+            name: &m.member.name,
+        })
+        .collect();
+
+    tmpl.render(context!(
+        name => cfg.name,
+        slug => cfg.slug,
+        years => utils::join_years(years),
+        leaderboard => leaderboard,
     ))
     .unwrap()
 }
@@ -1,22 +1,27 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use axum::response::Response;
+use chrono::{DateTime, Utc};
 use clap::Parser;
+use serde::Deserialize;
 use std::collections::HashMap;
 use std::net::SocketAddr;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::sync::Mutex;
 use tower_http::trace::TraceLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-use axum::{extract, http, response, response::IntoResponse, routing, Extension, Router};
+use axum::{extract, http, response, response::IntoResponse, routing, Extension, Json, Router};
 
 mod api;
 mod config;
 mod console;
 mod html;
+mod mock;
 mod model;
 mod parser;
+mod refresher;
+mod scoring;
+mod stats;
 mod utils;
 
 use config::{Config, LeaderboardConfig};
@@ -39,21 +44,63 @@ enum Opt {
     Console {
         /// TOML configuration file
         config: PathBuf,
+
+        /// Only print the leaderboard with this slug
+        #[clap(long)]
+        slug: Option<String>,
+
+        /// Read leaderboard JSON from this file (or - for stdin) instead of fetching it. It's
+        /// shown using the leaderboard given by --slug, or the first one with the same year
+        #[clap(long)]
+        from_file: Option<PathBuf>,
+
+        /// Show the standings as they were at this time, e.g. 2022-12-05T12:00:00Z
+        #[clap(long)]
+        as_of: Option<DateTime<Utc>>,
+
+        /// Show members that the filters of the leaderboard would hide
+        #[clap(long)]
+        all: bool,
+
+        /// Also print statistics for each day
+        #[clap(long)]
+        stats: bool,
+
+        /// Print all-time standings that combine every year of the leaderboard instead
+        #[clap(long, conflicts_with_all = ["from_file", "as_of"])]
+        all_time: bool,
+    },
+
+    /// Start a mock Advent of Code server that serves leaderboard JSON from a directory
+    Mock {
+        /// Directory with leaderboard JSON files named <year>-<id>.json
+        fixtures: PathBuf,
+
+        /// Bind address and port
+        #[clap(default_value = "127.0.0.1:3001")]
+        host: String,
+
+        /// Only accept this session cookie, any other session is redirected to a login page
+        #[clap(long)]
+        session: Option<String>,
     },
 }
 
-impl Opt {
-    fn config_path(&self) -> &Path {
-        match self {
-            Opt::Server { ref config, .. } => config,
-            Opt::Console { ref config, .. } => config,
-        }
-    }
+fn init_tracing() {
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::EnvFilter::new(
+            std::env::var("RUST_LOG")
+                .unwrap_or_else(|_| "advent_of_code_leaderboard=debug,tower_http=debug".into()),
+        ))
+        .with(tracing_subscriber::fmt::layer())
+        .init();
 }
 
 #[derive(Debug)]
 enum WebError {
     NotFound,
+    NotAvailable,
+    InvalidSession(api::Error),
     InternalError(anyhow::Error),
 }
 
@@ -62,56 +109,198 @@ where
     T: Into<anyhow::Error>,
 {
     fn from(error: T) -> Self {
-        Self::InternalError(error.into())
+        match error.into().downcast::<api::Error>() {
+            Ok(e) => Self::InvalidSession(e),
+            Err(e) => Self::InternalError(e),
+        }
     }
 }
 
-// API client that is shared across all requests (makes sure that we don't refresh simultaneously)
-type AocClient = Arc<Mutex<api::Client>>;
+type Metadata = HashMap<i32, HashMap<usize, MemberMetadata>>;
 
-async fn get_leaderboard(
-    extract::Path(slug): extract::Path<String>,
-    extract::Extension(cfg): extract::Extension<Arc<HashMap<String, LeaderboardConfig>>>,
-    extract::Extension(metadata): extract::Extension<
-        Arc<HashMap<i32, HashMap<usize, MemberMetadata>>>,
-    >,
-    extract::Extension(client): extract::Extension<AocClient>,
-) -> Result<response::Html<String>, WebError> {
-    let leaderboard_cfg = if let Some(cfg) = cfg.get(&slug) {
+/// Query parameters for pages that show standings
+#[derive(Debug, Deserialize)]
+struct StandingsQuery {
+    /// Show the standings as they were at this time
+    as_of: Option<DateTime<Utc>>,
+
+    /// Show members that are normally hidden, if present
+    all: Option<String>,
+}
+
+impl StandingsQuery {
+    fn view(&self) -> model::View {
+        model::View {
+            as_of: self.as_of,
+            show_all: self.all.is_some(),
+        }
+    }
+}
+
+/// Return one configuration for each year of the leaderboard with the given ID, oldest first. If
+/// a year is configured more than once, the first slug in alphabetical order is used.
+fn all_years<'a>(
+    configs: impl IntoIterator<Item = &'a LeaderboardConfig>,
+    id: usize,
+) -> Vec<&'a LeaderboardConfig> {
+    let mut configs: Vec<_> = configs.into_iter().filter(|l| l.id == id).collect();
+    configs.sort_by(|a, b| (a.year, &a.slug).cmp(&(b.year, &b.slug)));
+    configs.dedup_by_key(|l| l.year);
+    configs
+}
+
+/// Look up the configuration for a slug and the latest leaderboard for it
+async fn load_leaderboard<'a>(
+    slug: &str,
+    cfg: &'a HashMap<String, LeaderboardConfig>,
+    leaderboards: &refresher::Leaderboards,
+) -> Result<(&'a LeaderboardConfig, refresher::CachedLeaderboard), WebError> {
+    let leaderboard_cfg = if let Some(cfg) = cfg.get(slug) {
         cfg
     } else {
         return Err(WebError::NotFound);
     };
 
-    let leaderboard = {
-        client
-            .lock()
-            .await
-            .fetch(leaderboard_cfg.year, leaderboard_cfg.id)
-            .await?
-    };
-    let scoreboard = model::Scoreboard::from_leaderboard(&leaderboard);
+    match leaderboards.get(slug).await {
+        Ok(cached) => Ok((leaderboard_cfg, cached)),
+        Err(Some(e)) => match e.downcast_ref::<api::Error>() {
+            Some(e) => Err(WebError::InvalidSession(e.clone())),
+            None => Err(WebError::NotAvailable),
+        },
+        Err(None) => Err(WebError::NotAvailable),
+    }
+}
+
+async fn get_leaderboard(
+    extract::Path(slug): extract::Path<String>,
+    extract::Query(query): extract::Query<StandingsQuery>,
+    extract::Extension(cfg): extract::Extension<Arc<HashMap<String, LeaderboardConfig>>>,
+    extract::Extension(metadata): extract::Extension<Arc<Metadata>>,
+    extract::Extension(leaderboards): extract::Extension<refresher::Leaderboards>,
+) -> Result<response::Html<String>, WebError> {
+    let (leaderboard_cfg, cached) = load_leaderboard(&slug, &cfg, &leaderboards).await?;
 
     let empty_metadata = HashMap::new();
     let metadata = metadata
         .get(&leaderboard_cfg.year)
         .unwrap_or(&empty_metadata);
+    let scoreboard =
+        model::Scoreboard::with_view(&cached.leaderboard, leaderboard_cfg, metadata, query.view());
 
     Ok(response::Html(html::render_template(
         leaderboard_cfg,
         metadata,
         &scoreboard,
+        cached.error.is_some().then_some(cached.updated_at),
+        cached.error.as_deref(),
+    )))
+}
+
+async fn get_stats(
+    extract::Path(slug): extract::Path<String>,
+    extract::Query(query): extract::Query<StandingsQuery>,
+    extract::Extension(cfg): extract::Extension<Arc<HashMap<String, LeaderboardConfig>>>,
+    extract::Extension(metadata): extract::Extension<Arc<Metadata>>,
+    extract::Extension(leaderboards): extract::Extension<refresher::Leaderboards>,
+) -> Result<response::Html<String>, WebError> {
+    let (leaderboard_cfg, cached) = load_leaderboard(&slug, &cfg, &leaderboards).await?;
+
+    let empty_metadata = HashMap::new();
+    let metadata = metadata
+        .get(&leaderboard_cfg.year)
+        .unwrap_or(&empty_metadata);
+    let scoreboard =
+        model::Scoreboard::with_view(&cached.leaderboard, leaderboard_cfg, metadata, query.view());
+
+    Ok(response::Html(html::render_stats(
+        leaderboard_cfg,
+        &scoreboard,
+        &stats::day_stats(&scoreboard),
+        cached.error.is_some().then_some(cached.updated_at),
+        cached.error.as_deref(),
+    )))
+}
+
+async fn get_all_time(
+    extract::Path(slug): extract::Path<String>,
+    extract::Extension(cfg): extract::Extension<Arc<HashMap<String, LeaderboardConfig>>>,
+    extract::Extension(metadata): extract::Extension<Arc<Metadata>>,
+    extract::Extension(leaderboards): extract::Extension<refresher::Leaderboards>,
+) -> Result<response::Html<String>, WebError> {
+    let id = match cfg.get(&slug) {
+        Some(leaderboard_cfg) => leaderboard_cfg.id,
+        None => return Err(WebError::NotFound),
+    };
+
+    // Years that haven't been fetched yet are left out rather than failing the whole page
+    let empty_metadata = HashMap::new();
+    let mut latest = None;
+    let mut scoreboards = Vec::new();
+    for leaderboard_cfg in all_years(cfg.values(), id) {
+        if let Ok(cached) = leaderboards.get(&leaderboard_cfg.slug).await {
+            let metadata = metadata
+                .get(&leaderboard_cfg.year)
+                .unwrap_or(&empty_metadata);
+            scoreboards.push(model::Scoreboard::from_leaderboard(
+                &cached.leaderboard,
+                leaderboard_cfg,
+                metadata,
+            ));
+            latest = Some(leaderboard_cfg);
+        }
+    }
+    let latest = latest.ok_or(WebError::NotAvailable)?;
+
+    let years: Vec<_> = scoreboards.iter().map(|s| s.year).collect();
+    Ok(response::Html(html::render_all_time(
+        latest,
+        &years,
+        &model::all_time(&scoreboards),
+    )))
+}
+
+/// Return how the place and score of every member changed over time as JSON
+async fn get_history(
+    extract::Path(slug): extract::Path<String>,
+    extract::Extension(cfg): extract::Extension<Arc<HashMap<String, LeaderboardConfig>>>,
+    extract::Extension(metadata): extract::Extension<Arc<Metadata>>,
+    extract::Extension(leaderboards): extract::Extension<refresher::Leaderboards>,
+) -> Result<Json<Vec<model::MemberHistory>>, WebError> {
+    let (leaderboard_cfg, cached) = load_leaderboard(&slug, &cfg, &leaderboards).await?;
+
+    let empty_metadata = HashMap::new();
+    let metadata = metadata
+        .get(&leaderboard_cfg.year)
+        .unwrap_or(&empty_metadata);
+    Ok(Json(model::history(
+        &cached.leaderboard,
+        leaderboard_cfg,
+        metadata,
     )))
 }
 
 impl IntoResponse for WebError {
     fn into_response(self) -> Response {
         let (status, error_message) = match self {
-            Self::NotFound => (http::StatusCode::NOT_FOUND, "404 Not Found"),
-            Self::InternalError(_) => (
-                http::StatusCode::INTERNAL_SERVER_ERROR,
-                "500 Internal Server Error",
+            Self::NotFound => (http::StatusCode::NOT_FOUND, "404 Not Found".to_owned()),
+            Self::NotAvailable => (
+                http::StatusCode::SERVICE_UNAVAILABLE,
+                "503 Service Unavailable\n\nThe leaderboard hasn't been fetched yet, please try again later".to_owned(),
             ),
+            Self::InvalidSession(e) => {
+                tracing::error!("{}", e);
+                (
+                    http::StatusCode::SERVICE_UNAVAILABLE,
+                    format!("503 Service Unavailable\n\n{}", e),
+                )
+            }
+            Self::InternalError(e) => {
+                tracing::error!("{:#}", e);
+                (
+                    http::StatusCode::INTERNAL_SERVER_ERROR,
+                    "500 Internal Server Error".to_owned(),
+                )
+            }
         };
         (status, error_message).into_response()
     }
@@ -119,20 +308,12 @@ impl IntoResponse for WebError {
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let opts = Opt::parse();
-    let config = Config::from_file(opts.config_path())?;
-
-    match opts {
-        Opt::Server { host, .. } => {
-            tracing_subscriber::registry()
-                .with(tracing_subscriber::EnvFilter::new(
-                    std::env::var("RUST_LOG").unwrap_or_else(|_| {
-                        "advent_of_code_leaderboard=debug,tower_http=debug".into()
-                    }),
-                ))
-                .with(tracing_subscriber::fmt::layer())
-                .init();
-            let client = api::Client::new(config.session, config.cache_dir);
+    match Opt::parse() {
+        Opt::Server { config, host } => {
+            let config = Config::from_file(config)?;
+            init_tracing();
+            let client = Arc::new(config.client());
+            let leaderboards = refresher::spawn(client, config.leaderboard.iter().cloned());
             let metadata = config.metadata;
             let config = config
                 .leaderboard
@@ -142,30 +323,153 @@ async fn main() -> Result<()> {
 
             let app = Router::new()
                 .route("/:slug", routing::get(get_leaderboard))
+                .route("/:slug/stats", routing::get(get_stats))
+                .route("/:slug/history", routing::get(get_history))
+                .route("/:slug/all-time", routing::get(get_all_time))
                 .layer(TraceLayer::new_for_http())
                 .layer(Extension(Arc::new(config)))
                 .layer(Extension(Arc::new(metadata)))
-                .layer(Extension(Arc::new(Mutex::new(client))));
+                .layer(Extension(leaderboards));
 
             let bind: SocketAddr = host.parse()?;
             tracing::info!("Listening on {}", &bind);
             let listener = tokio::net::TcpListener::bind(bind).await.unwrap();
             axum::serve(listener, app).await.unwrap();
         }
-        Opt::Console { .. } => {
-            let client = api::Client::new(config.session, config.cache_dir);
+        Opt::Console {
+            config,
+            slug,
+            from_file,
+            as_of,
+            all,
+            stats,
+            all_time,
+        } => {
+            let config = Config::from_file(config)?;
+            let client = Arc::new(config.client());
+
+            let leaderboards = if let Some(path) = from_file {
+                let snapshot = api::read_snapshot(&path)?;
+                let year = snapshot.leaderboard.event;
+                let leaderboard_cfg = config
+                    .leaderboard
+                    .iter()
+                    .find(|l| match &slug {
+                        Some(slug) => &l.slug == slug,
+                        None => l.year == year,
+                    })
+                    .ok_or_else(|| match &slug {
+                        Some(slug) => anyhow!("No leaderboard with slug {}", slug),
+                        None => anyhow!("No leaderboard configured for {}", year),
+                    })?;
+                vec![(leaderboard_cfg, (snapshot, None))]
+            } else {
+                let mut selected: Vec<_> = config
+                    .leaderboard
+                    .iter()
+                    .filter(|l| slug.as_ref().is_none_or(|slug| &l.slug == slug))
+                    .collect();
+                if all_time {
+                    let mut ids = Vec::new();
+                    for l in selected.iter() {
+                        if !ids.contains(&l.id) {
+                            ids.push(l.id);
+                        }
+                    }
+                    selected = ids
+                        .into_iter()
+                        .flat_map(|id| all_years(&config.leaderboard, id))
+                        .collect();
+                }
+
+                // Fetch all leaderboards in parallel, but print them in the configured order
+                let fetches: Vec<_> = selected
+                    .into_iter()
+                    .map(|l| {
+                        let client = client.clone();
+                        let cfg = l.clone();
+                        (
+                            l,
+                            tokio::spawn(async move { client.fetch_leaderboard(&cfg).await }),
+                        )
+                    })
+                    .collect();
+
+                let mut leaderboards = Vec::new();
+                for (leaderboard_cfg, fetch) in fetches {
+                    leaderboards.push((leaderboard_cfg, fetch.await??));
+                }
+                leaderboards
+            };
+
             let empty_metadata = HashMap::new();
-            for leaderboard_cfg in config.leaderboard.into_iter() {
-                let leaderboard = client
-                    .fetch(leaderboard_cfg.year, leaderboard_cfg.id)
-                    .await?;
-                let scoreboard = model::Scoreboard::from_leaderboard(&leaderboard);
+            let mut all_time_scoreboards: Vec<(&LeaderboardConfig, Vec<_>)> = Vec::new();
+            for (leaderboard_cfg, (snapshot, error)) in leaderboards {
+                if let Some(e) = &error {
+                    eprintln!(
+                        "Unable to refresh {}, showing data from {}: {:#}",
+                        leaderboard_cfg.name, snapshot.fetched_at, e
+                    );
+                }
                 let metadata = config
                     .metadata
                     .get(&leaderboard_cfg.year)
                     .unwrap_or(&empty_metadata);
-                console::render_template(&leaderboard_cfg, metadata, &scoreboard);
+                if all_time {
+                    let scoreboard = model::Scoreboard::from_leaderboard(
+                        &snapshot.leaderboard,
+                        leaderboard_cfg,
+                        metadata,
+                    );
+
+                    // Years are fetched oldest first, so the last configuration is the newest
+                    match all_time_scoreboards
+                        .iter_mut()
+                        .find(|(l, _)| l.id == leaderboard_cfg.id)
+                    {
+                        Some((latest, scoreboards)) => {
+                            *latest = leaderboard_cfg;
+                            scoreboards.push(scoreboard);
+                        }
+                        None => all_time_scoreboards.push((leaderboard_cfg, vec![scoreboard])),
+                    }
+                    continue;
+                }
+
+                let view = model::View {
+                    as_of,
+                    show_all: all,
+                };
+                let scoreboard = model::Scoreboard::with_view(
+                    &snapshot.leaderboard,
+                    leaderboard_cfg,
+                    metadata,
+                    view,
+                );
+                console::render_template(
+                    leaderboard_cfg,
+                    metadata,
+                    &scoreboard,
+                    error.is_some().then_some(snapshot.fetched_at),
+                );
+                if stats {
+                    println!();
+                    console::render_stats(&scoreboard, &stats::day_stats(&scoreboard));
+                }
             }
+
+            for (leaderboard_cfg, scoreboards) in all_time_scoreboards {
+                let years: Vec<_> = scoreboards.iter().map(|s| s.year).collect();
+                console::render_all_time(leaderboard_cfg, &years, &model::all_time(&scoreboards));
+            }
+        }
+        Opt::Mock {
+            fixtures,
+            host,
+            session,
+        } => {
+            init_tracing();
+            mock::serve(fixtures, session, host.parse()?).await?;
         }
     };
 
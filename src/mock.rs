@@ -0,0 +1,79 @@
+use anyhow::Result;
+use axum::{extract, http, response, response::IntoResponse, routing, Extension, Router};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tower_http::trace::TraceLayer;
+
+/// Settings for the mock Advent of Code server
+#[derive(Debug)]
+struct MockConfig {
+    fixtures: PathBuf,
+    session: Option<String>,
+}
+
+/// Return true if the request carries the expected session cookie, or if we accept any session
+fn is_authorized(headers: &http::HeaderMap, session: &Option<String>) -> bool {
+    let session = match session {
+        Some(session) => session,
+        None => return true,
+    };
+
+    headers
+        .get_all(http::header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|c| c.trim().split_once('='))
+        .any(|(k, v)| k == "session" && v == session)
+}
+
+/// Serve leaderboard JSON from `<fixtures>/<year>-<id>.json`, mimicking the private leaderboard
+/// API of Advent of Code
+async fn get_leaderboard(
+    extract::Path((year, file)): extract::Path<(i32, String)>,
+    extract::Extension(cfg): extract::Extension<Arc<MockConfig>>,
+    headers: http::HeaderMap,
+) -> response::Response {
+    // Advent of Code redirects to the leaderboard overview when the session is wrong
+    if !is_authorized(&headers, &cfg.session) {
+        return response::Redirect::to(&format!("/{}/leaderboard/private", year)).into_response();
+    }
+
+    let id: usize = match file.strip_suffix(".json").and_then(|id| id.parse().ok()) {
+        Some(id) => id,
+        None => return http::StatusCode::NOT_FOUND.into_response(),
+    };
+
+    let path = cfg.fixtures.join(format!("{}-{}.json", year, id));
+    match tokio::fs::read_to_string(&path).await {
+        Ok(json) => ([(http::header::CONTENT_TYPE, "application/json")], json).into_response(),
+        Err(e) => {
+            tracing::warn!("Unable to read fixture {}: {}", path.display(), e);
+            http::StatusCode::NOT_FOUND.into_response()
+        }
+    }
+}
+
+/// Stand in for the login page that Advent of Code shows for invalid sessions
+async fn get_login_page() -> response::Html<&'static str> {
+    response::Html("<!DOCTYPE html><html><body>[Log In]</body></html>")
+}
+
+/// Run a server that serves fixture leaderboards the same way Advent of Code does. If a session
+/// is given, requests with any other session cookie are rejected.
+pub async fn serve(fixtures: PathBuf, session: Option<String>, bind: SocketAddr) -> Result<()> {
+    let app = Router::new()
+        .route(
+            "/:year/leaderboard/private/view/:file",
+            routing::get(get_leaderboard),
+        )
+        .route("/:year/leaderboard/private", routing::get(get_login_page))
+        .layer(TraceLayer::new_for_http())
+        .layer(Extension(Arc::new(MockConfig { fixtures, session })));
+
+    tracing::info!("Mock Advent of Code listening on {}", &bind);
+    let listener = tokio::net::TcpListener::bind(bind).await?;
+    axum::serve(listener, app).await?;
+    Ok(())
+}
@@ -1,7 +1,12 @@
-use std::cmp;
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::HashMap;
 
-use crate::parser::{Day, Leaderboard};
-use crate::utils::{release_time, score_puzzle};
+use crate::config::{LeaderboardConfig, MemberMetadata};
+use crate::parser::{self, Day, Leaderboard, Star};
+use crate::scoring::{self, Scoring, StarContext};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Stars {
@@ -10,78 +15,971 @@ pub enum Stars {
     Both,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Member {
     pub id: usize,
     pub name: String,
-    pub github: Option<String>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct MemberScore {
     pub member: Member,
-    pub stars: [Stars; 25],
+    pub place: usize,
+    pub stars: Vec<Stars>,
     pub score: usize,
+
+    /// Points for each day, not including bonuses
+    pub day_scores: Vec<usize>,
+
+    /// Number of stars that count towards the score
+    pub num_solved: usize,
+
+    /// Total time from release until each star was awarded
+    pub solve_time: Duration,
+
+    /// The most recent star that counts towards the score
+    pub last_star: Option<Star>,
+
+    /// Consecutive days where both stars were awarded within 24 hours from release
+    pub current_streak: usize,
+    pub longest_streak: usize,
+
+    /// IDs of the leaderboards the member is on, if several are combined
+    pub boards: Vec<usize>,
+
+    /// Stars and score on the official Advent of Code leaderboard
+    pub num_stars: usize,
+    pub local_score: usize,
+    pub global_score: usize,
+    pub last_star_ts: Option<DateTime<Utc>>,
+
+    /// How the member can still finish, only set while the event is in progress
+    pub outlook: Option<Outlook>,
+}
+
+/// Where a member can still finish, ignoring tie-breakers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Contention {
+    /// Nobody can catch up with the member anymore
+    Clinched,
+    CanWin,
+    CanFinishTop3,
+    Eliminated,
+}
+
+impl Contention {
+    /// Short marker to show next to the member
+    pub fn marker(&self) -> &'static str {
+        match self {
+            Self::Clinched => "C",
+            Self::CanWin => "W",
+            Self::CanFinishTop3 => "3",
+            Self::Eliminated => "-",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::Clinched => "Has clinched first place",
+            Self::CanWin => "Can still win",
+            Self::CanFinishTop3 => "Can still finish in the top 3",
+            Self::Eliminated => "Can't finish in the top 3 anymore",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Outlook {
+    /// The best score the member can reach if they get every remaining star as soon as they can
+    pub best_score: usize,
+    pub contention: Contention,
+}
+
+#[derive(Debug)]
+pub struct TeamScore {
+    pub name: String,
+    pub place: usize,
+    pub score: usize,
+
+    /// Team members that are on the leaderboard, including those that are hidden
+    pub members: Vec<Member>,
+}
+
+/// Standings of a member across every year of a leaderboard
+#[derive(Debug)]
+pub struct AllTimeScore {
+    pub member: Member,
+    pub place: usize,
+    pub score: usize,
+    pub num_stars: usize,
+
+    /// Years where the member got at least one star
+    pub years: Vec<i32>,
+
+    /// Best place and the year it was achieved, the most recent if there are several
+    pub best_finish: Option<(usize, i32)>,
+}
+
+/// A star awarded to a member
+#[derive(Debug, Clone, Copy)]
+pub struct Solve {
+    pub member_id: usize,
+    pub day: usize,
+    pub part: usize,
+    pub star: Star,
 }
 
 #[derive(Debug)]
 pub struct Scoreboard {
     pub year: i32,
+
+    /// Number of puzzles in this event
+    pub days: usize,
+
+    /// Days that don't count towards the score, indexed by day - 1
+    pub voided: Vec<bool>,
+    pub owner_id: Option<usize>,
     pub scores: Vec<MemberScore>,
+
+    /// Names of every member by ID, including those that are hidden
+    pub names: HashMap<usize, String>,
+
+    /// Team standings, if the leaderboard has teams
+    pub teams: Vec<TeamScore>,
+
+    /// Every star on the leaderboard in the order they were awarded
+    pub solves: Vec<Solve>,
+
+    /// Only stars awarded up to this time are included, if set
+    pub as_of: Option<DateTime<Utc>>,
+
+    /// Number of members that are left out by the filters of the leaderboard
+    pub num_hidden: usize,
 }
 
-impl Scoreboard {
-    pub fn from_leaderboard(leaderboard: &Leaderboard) -> Self {
-        let year = leaderboard.event;
-        let mut scores: Vec<_> = leaderboard
+/// Which standings to show
+#[derive(Debug, Clone, Copy, Default)]
+pub struct View {
+    /// Only include stars awarded up to this time
+    pub as_of: Option<DateTime<Utc>>,
+
+    /// Include members that the filters of the leaderboard would hide
+    pub show_all: bool,
+}
+
+/// Place and score of a member at a point in time
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct HistoryPoint {
+    pub ts: DateTime<Utc>,
+    pub place: usize,
+    pub score: usize,
+}
+
+/// How the place and score of a member changed as stars were awarded
+#[derive(Debug, Serialize)]
+pub struct MemberHistory {
+    pub id: usize,
+    pub name: String,
+
+    /// A point for every time the place or score changed
+    pub points: Vec<HistoryPoint>,
+}
+
+/// Return the current and longest streak of days where the member got both stars within 24 hours
+/// from release, given when they got both stars for each day. A day that is still within its
+/// first 24 hours, or is voided, doesn't break the current streak.
+fn streaks(
+    completed: &[Option<DateTime<Utc>>],
+    cfg: &LeaderboardConfig,
+    metadata: Option<&MemberMetadata>,
+    now: DateTime<Utc>,
+) -> (usize, usize) {
+    let mut current = 0;
+    let mut longest = 0;
+    for (day, completed) in (1..).zip(completed) {
+        let released = cfg.release_time(day, metadata);
+        if released > now {
+            break;
+        }
+        if cfg.is_voided(day) {
+            continue;
+        }
+
+        let deadline = released + Duration::days(1);
+        if completed.is_some_and(|ts| ts < deadline) {
+            current += 1;
+            longest = longest.max(current);
+        } else if deadline <= now {
+            current = 0;
+        }
+    }
+    (current, longest)
+}
+
+/// Return the star that was awarded first
+fn earliest(a: Star, b: Star) -> Star {
+    if (b.ts, b.index) < (a.ts, a.index) {
+        b
+    } else {
+        a
+    }
+}
+
+/// Merge the accounts that members have listed as aliases into their main account, keeping the
+/// earliest time for every star
+fn merge_aliases<'a>(
+    leaderboard: &'a Leaderboard,
+    metadata: &HashMap<usize, MemberMetadata>,
+) -> Cow<'a, Leaderboard> {
+    let aliases: Vec<_> = metadata
+        .iter()
+        .flat_map(|(&id, m)| m.aliases.iter().map(move |&alias| (id, alias)))
+        .filter(|(_, alias)| leaderboard.members.contains_key(alias))
+        .collect();
+    if aliases.is_empty() {
+        return Cow::Borrowed(leaderboard);
+    }
+
+    let mut leaderboard = leaderboard.clone();
+    for (id, alias) in aliases {
+        let alias = leaderboard.members.remove(&alias).unwrap();
+        if leaderboard.owner_id == Some(alias.id) {
+            leaderboard.owner_id = Some(id);
+        }
+
+        let member = match leaderboard.members.get_mut(&id) {
+            Some(member) => member,
+            None => {
+                // The main account isn't on the leaderboard, so the alias takes its place
+                leaderboard
+                    .members
+                    .insert(id, parser::Member { id, ..alias });
+                continue;
+            }
+        };
+        for (day, parts) in alias.completion_day_level {
+            let merged = match member.completion_day_level.get(&day) {
+                Some(existing) => Day {
+                    part1: earliest(existing.part1, parts.part1),
+                    part2: match (existing.part2, parts.part2) {
+                        (Some(a), Some(b)) => Some(earliest(a, b)),
+                        (a, b) => a.or(b),
+                    },
+                },
+                None => parts,
+            };
+            member.completion_day_level.insert(day, merged);
+        }
+
+        // Official scores can't be merged, so we keep the best one
+        member.name = member.name.take().or(alias.name);
+        member.stars = member
+            .completion_day_level
+            .values()
+            .map(|d| 1 + d.part2.is_some() as usize)
+            .sum();
+        member.local_score = member.local_score.max(alias.local_score);
+        member.global_score = member.global_score.max(alias.global_score);
+        member.last_star_ts = member.last_star_ts.max(alias.last_star_ts);
+        for board in alias.boards {
+            if !member.boards.contains(&board) {
+                member.boards.push(board);
+            }
+        }
+    }
+    Cow::Owned(leaderboard)
+}
+
+/// Builds member scores by applying stars one at a time in the order they were awarded, which
+/// lets us see the standings at any point in time
+struct Replay<'a> {
+    cfg: &'a LeaderboardConfig,
+    metadata: &'a HashMap<usize, MemberMetadata>,
+    scoring: Box<dyn Scoring>,
+    num_members: usize,
+    scores: Vec<MemberScore>,
+
+    /// Position in scores by member ID
+    positions: HashMap<usize, usize>,
+
+    /// When each member got both stars for each day, indexed like scores
+    completed: Vec<Vec<Option<DateTime<Utc>>>>,
+
+    /// How many members have gotten each star so far
+    num_solvers: HashMap<(usize, usize), usize>,
+}
+
+impl<'a> Replay<'a> {
+    /// Return a replay where no stars have been awarded, and every star on the leaderboard in the
+    /// order they were awarded
+    fn new(
+        leaderboard: &Leaderboard,
+        cfg: &'a LeaderboardConfig,
+        metadata: &'a HashMap<usize, MemberMetadata>,
+    ) -> (Self, Vec<Solve>) {
+        let days = cfg.num_days();
+        let mut solves: Vec<_> = leaderboard
+            .members
+            .values()
+            .flat_map(|m| {
+                m.completion_day_level
+                    .iter()
+                    .filter(|(&day, _)| day <= days)
+                    .flat_map(move |(&day, parts)| {
+                        std::iter::once((1, parts.part1))
+                            .chain(parts.part2.map(|star| (2, star)))
+                            .map(move |(part, star)| Solve {
+                                member_id: m.id,
+                                day,
+                                part,
+                                star,
+                            })
+                    })
+            })
+            .collect();
+
+        // Star index is the exact order, but isn't available for old leaderboards
+        solves.sort_by_key(|s| (s.star.ts, s.star.index, s.member_id, s.day, s.part));
+
+        let scores: Vec<_> = leaderboard
             .members
             .values()
-            .map(|leaderboard_member| {
-                let member = Member {
+            .map(|leaderboard_member| MemberScore {
+                member: Member {
                     id: leaderboard_member.id,
                     name: leaderboard_member
                         .name
                         .as_ref()
                         .map(ToOwned::to_owned)
                         .unwrap_or_else(|| format!("(anonymous user #{})", leaderboard_member.id)),
-                    github: None,
+                },
+                place: 0,
+                stars: vec![Stars::None; days],
+                score: 0,
+                day_scores: vec![0; days],
+                num_solved: 0,
+                solve_time: Duration::zero(),
+                last_star: None,
+                current_streak: 0,
+                longest_streak: 0,
+                num_stars: 0,
+                local_score: 0,
+                global_score: leaderboard_member.global_score,
+                boards: leaderboard_member.boards.clone(),
+                last_star_ts: None,
+                outlook: None,
+            })
+            .collect();
+
+        let replay = Self {
+            cfg,
+            metadata,
+            scoring: scoring::from_config(cfg),
+            num_members: scores.len(),
+            positions: scores
+                .iter()
+                .enumerate()
+                .map(|(i, m)| (m.member.id, i))
+                .collect(),
+            completed: vec![vec![None; days]; scores.len()],
+            scores,
+            num_solvers: HashMap::new(),
+        };
+        (replay, solves)
+    }
+
+    /// Award a star, which must be done in the order they were awarded. Return the position of
+    /// the member in scores.
+    fn apply(&mut self, solve: &Solve) -> usize {
+        let position = self.positions[&solve.member_id];
+        let rank = self.num_solvers.entry((solve.day, solve.part)).or_insert(0);
+        let ctx = StarContext {
+            part: solve.part,
+            released: self
+                .cfg
+                .release_time(solve.day, self.metadata.get(&solve.member_id)),
+            completed: solve.star.ts,
+            rank: *rank,
+            num_members: self.num_members,
+        };
+        *rank += 1;
+
+        let member = &mut self.scores[position];
+        member.num_stars += 1;
+        member.local_score += ctx.num_members - ctx.rank;
+        member.last_star_ts = Some(solve.star.ts);
+        if solve.part == 2 {
+            member.stars[solve.day - 1] = Stars::Both;
+            self.completed[position][solve.day - 1] = Some(solve.star.ts);
+        } else if member.stars[solve.day - 1] == Stars::None {
+            member.stars[solve.day - 1] = Stars::First;
+        }
+
+        if !self.cfg.is_voided(solve.day) {
+            let points = self.scoring.score_star(&ctx);
+            let points = (points as f64 * self.cfg.day_multiplier(solve.day)).round() as usize;
+            member.score += points;
+            member.day_scores[solve.day - 1] += points;
+            member.num_solved += 1;
+            member.solve_time += ctx.completed - ctx.released;
+            member.last_star = Some(solve.star);
+        }
+        position
+    }
+
+    /// Recompute the streaks of the member at the given position, as of the given time
+    fn update_streaks(&mut self, position: usize, now: DateTime<Utc>) {
+        let member = &mut self.scores[position];
+        let (current, longest) = streaks(
+            &self.completed[position],
+            self.cfg,
+            self.metadata.get(&member.member.id),
+            now,
+        );
+        member.score = member.score - self.cfg.streak_bonus * member.longest_streak
+            + self.cfg.streak_bonus * longest;
+        member.current_streak = current;
+        member.longest_streak = longest;
+    }
+
+    /// Return the member at the given position as if they got every remaining star as soon as
+    /// they could after the given time, which is the best they can still do
+    fn best_case(&self, position: usize, now: DateTime<Utc>) -> MemberScore {
+        let mut member = self.scores[position].clone();
+        let metadata = self.metadata.get(&member.member.id);
+        let mut completed = self.completed[position].clone();
+        for day in 1..=member.stars.len() {
+            let parts = match member.stars[day - 1] {
+                Stars::None => 1..=2,
+                Stars::First => 2..=2,
+                Stars::Both => continue,
+            };
+            let released = self.cfg.release_time(day, metadata);
+            let earliest = now.max(released);
+            member.stars[day - 1] = Stars::Both;
+            completed[day - 1] = Some(earliest);
+            if self.cfg.is_voided(day) {
+                continue;
+            }
+
+            for part in parts {
+                let ctx = StarContext {
+                    part,
+                    released,
+                    completed: earliest,
+                    rank: self.num_solvers.get(&(day, part)).copied().unwrap_or(0),
+                    num_members: self.num_members,
                 };
+                let points = self.scoring.best_star_score(&ctx);
+                let points = (points as f64 * self.cfg.day_multiplier(day)).round() as usize;
+                member.score += points;
+                member.day_scores[day - 1] += points;
+                member.num_solved += 1;
+                member.solve_time += earliest - released;
+            }
+        }
 
-                let (stars, score) =
-                    (1..=25usize).fold(([Stars::None; 25], 0), |(mut stars, mut score), day| {
-                        let released = release_time(year, day as u32).unwrap();
-                        let parts = leaderboard_member.completion_day_level.get(&day);
-
-                        score += match parts {
-                            Some(Day {
-                                part1,
-                                part2: Some(part2),
-                            }) => {
-                                stars[day - 1] = Stars::Both;
-                                score_puzzle(*part1 - released) + score_puzzle(*part2 - released)
-                            }
-                            Some(Day { part1, part2: None }) => {
-                                stars[day - 1] = Stars::First;
-                                score_puzzle(*part1 - released)
-                            }
-                            None => 0,
-                        };
-
-                        (stars, score)
-                    });
-
-                MemberScore {
-                    member,
-                    stars,
-                    score,
+        // Look past the last release so that streaks only count days that are actually solved
+        let end = self.cfg.release_time(member.stars.len(), metadata) + Duration::days(1);
+        let (current, longest) = streaks(&completed, self.cfg, metadata, end);
+        member.score = member.score - self.cfg.streak_bonus * member.longest_streak
+            + self.cfg.streak_bonus * longest;
+        member.current_streak = current;
+        member.longest_streak = longest;
+        member
+    }
+
+    /// Return how each of the placed members can still finish as of the given time. Members
+    /// that aren't placed can still catch up, so they're taken into account too. Tie-breakers
+    /// may change before the end, so members that can reach the same score as someone else are
+    /// considered able to finish ahead of them.
+    fn outlooks(&self, placed: &[(usize, usize)], now: DateTime<Utc>) -> Vec<Outlook> {
+        let best: Vec<_> = (0..self.scores.len())
+            .map(|position| self.best_case(position, now))
+            .collect();
+        placed
+            .iter()
+            .map(|&(position, _)| {
+                let current = &self.scores[position];
+                let others = || (0..self.scores.len()).filter(move |&other| other != position);
+
+                // Scores never decrease, so the current standings are the worst case
+                let clinched =
+                    others().all(|other| self.scoring.compare(current, &best[other]).is_lt());
+                let out_of_reach = others()
+                    .filter(|&other| {
+                        self.scoring
+                            .compare(&self.scores[other], &best[position])
+                            .is_lt()
+                    })
+                    .count();
+                let contention = match out_of_reach {
+                    _ if clinched => Contention::Clinched,
+                    0 => Contention::CanWin,
+                    1 | 2 => Contention::CanFinishTop3,
+                    _ => Contention::Eliminated,
+                };
+                Outlook {
+                    best_score: best[position].score,
+                    contention,
                 }
             })
-            .collect();
+            .collect()
+    }
+
+    fn compare(&self, a: &MemberScore, b: &MemberScore) -> Ordering {
+        self.scoring
+            .compare(a, b)
+            .then_with(|| self.cfg.tie_breaker.compare(a, b))
+    }
 
+    /// Return the positions in scores of the included members from first to last place, along
+    /// with the place
+    fn placed(&self, include: impl Fn(&MemberScore) -> bool) -> Vec<(usize, usize)> {
         // Use ID as a discirminator to ensure deterministic result
-        scores.sort_by_key(|member| (cmp::Reverse(member.score), member.member.id));
+        let mut positions: Vec<_> = (0..self.scores.len())
+            .filter(|&position| include(&self.scores[position]))
+            .collect();
+        positions.sort_by(|&a, &b| {
+            let (a, b) = (&self.scores[a], &self.scores[b]);
+            self.compare(a, b).then(a.member.id.cmp(&b.member.id))
+        });
+
+        // Members that are equal even after breaking ties share the same place
+        let mut placed: Vec<(usize, usize)> = Vec::with_capacity(positions.len());
+        for (i, &position) in positions.iter().enumerate() {
+            let place = match placed.last() {
+                Some(&(prev, place))
+                    if self
+                        .compare(&self.scores[prev], &self.scores[position])
+                        .is_eq() =>
+                {
+                    place
+                }
+                _ => i + 1,
+            };
+            placed.push((position, place));
+        }
+        placed
+    }
+}
+
+/// Return true if the filters of the leaderboard hide the member
+fn is_hidden(
+    member: &MemberScore,
+    cfg: &LeaderboardConfig,
+    metadata: &HashMap<usize, MemberMetadata>,
+    now: DateTime<Utc>,
+) -> bool {
+    let inactive = cfg.hide_inactive_days.is_some_and(|days| {
+        member
+            .last_star_ts
+            .is_none_or(|ts| now - ts > Duration::days(days.into()))
+    });
+    inactive
+        || member.num_stars < cfg.min_stars
+        || metadata.get(&member.member.id).is_some_and(|m| m.hidden)
+}
+
+/// Combine the scores of the members of each team and place the teams
+fn team_scores(cfg: &LeaderboardConfig, scores: &[MemberScore]) -> Vec<TeamScore> {
+    let mut teams: Vec<_> = cfg
+        .teams
+        .iter()
+        .map(|team| {
+            let mut members: Vec<_> = scores
+                .iter()
+                .filter(|m| team.members.contains(&m.member.id))
+                .collect();
+            members.sort_by_key(|m| (std::cmp::Reverse(m.score), m.member.id));
+            TeamScore {
+                name: team.name.clone(),
+                place: 0,
+                score: cfg.team_aggregation.aggregate(&members),
+                members: members.iter().map(|m| m.member.clone()).collect(),
+            }
+        })
+        .collect();
+
+    // Keep the configured order for teams with the same score, which share the same place
+    teams.sort_by_key(|t| std::cmp::Reverse(t.score));
+    for i in 0..teams.len() {
+        teams[i].place = if i > 0 && teams[i - 1].score == teams[i].score {
+            teams[i - 1].place
+        } else {
+            i + 1
+        };
+    }
+    teams
+}
+
+impl Scoreboard {
+    /// Return the name of the member with the given ID, even if they're hidden
+    pub fn member_name(&self, member_id: usize) -> Option<&str> {
+        self.names.get(&member_id).map(String::as_str)
+    }
+
+    /// Return the current standings of every member
+    pub fn from_leaderboard(
+        leaderboard: &Leaderboard,
+        cfg: &LeaderboardConfig,
+        metadata: &HashMap<usize, MemberMetadata>,
+    ) -> Self {
+        let view = View {
+            show_all: true,
+            ..View::default()
+        };
+        Self::with_view(leaderboard, cfg, metadata, view)
+    }
+
+    /// Return the standings as seen through the given view
+    pub fn with_view(
+        leaderboard: &Leaderboard,
+        cfg: &LeaderboardConfig,
+        metadata: &HashMap<usize, MemberMetadata>,
+        view: View,
+    ) -> Self {
+        let at = view.as_of;
+        let leaderboard = merge_aliases(leaderboard, metadata);
+        let (mut replay, mut solves) = Replay::new(&leaderboard, cfg, metadata);
+        if let Some(at) = at {
+            solves.retain(|s| s.star.ts <= at);
+        }
+        for solve in solves.iter() {
+            replay.apply(solve);
+        }
+
+        let now = at.unwrap_or_else(Utc::now);
+        for position in 0..replay.scores.len() {
+            replay.update_streaks(position, now);
+        }
+
+        // The official numbers are more accurate than our own, since they include days we don't
+        // count, but they're only valid for the current standings
+        if at.is_none() {
+            for member in replay.scores.iter_mut() {
+                let official = &leaderboard.members[&member.member.id];
+                member.num_stars = official.stars;
+                member.local_score = official.local_score;
+                member.last_star_ts = official.last_star_ts;
+            }
+        }
+
+        let placed = replay.placed(|m| view.show_all || !is_hidden(m, cfg, metadata, now));
+        let num_hidden = replay.scores.len() - placed.len();
+
+        // Filters only decide who is shown, hidden members still count towards their team
+        let teams = team_scores(cfg, &replay.scores);
+        let names = replay
+            .scores
+            .iter()
+            .map(|m| (m.member.id, m.member.name.clone()))
+            .collect();
+
+        // The outlook is only interesting while there are stars left to get on time
+        let in_progress = cfg.release_time(1, None) <= now
+            && now < cfg.release_time(cfg.num_days(), None) + Duration::days(1);
+        let mut outlooks = in_progress.then(|| replay.outlooks(&placed, now).into_iter());
+
+        let mut scores: Vec<_> = replay.scores.into_iter().map(Some).collect();
+        let scores = placed
+            .into_iter()
+            .map(|(position, place)| {
+                let mut member = scores[position].take().unwrap();
+                member.place = place;
+                member.outlook = outlooks.as_mut().and_then(Iterator::next);
+                member
+            })
+            .collect::<Vec<_>>();
+
+        Self {
+            year: leaderboard.event,
+            days: cfg.num_days(),
+            voided: (1..=cfg.num_days()).map(|day| cfg.is_voided(day)).collect(),
+            owner_id: leaderboard.owner_id,
+            teams,
+            scores,
+            names,
+            solves,
+            as_of: at,
+            num_hidden,
+        }
+    }
+}
+
+/// Replay every star on the leaderboard in the order they were awarded, and return how the place
+/// and score of each member changed. Members are in the order of the current standings.
+pub fn history(
+    leaderboard: &Leaderboard,
+    cfg: &LeaderboardConfig,
+    metadata: &HashMap<usize, MemberMetadata>,
+) -> Vec<MemberHistory> {
+    let leaderboard = merge_aliases(leaderboard, metadata);
+    let (mut replay, solves) = Replay::new(&leaderboard, cfg, metadata);
+    let mut history: Vec<_> = replay
+        .scores
+        .iter()
+        .map(|m| MemberHistory {
+            id: m.member.id,
+            name: m.member.name.clone(),
+            points: Vec::new(),
+        })
+        .collect();
+
+    for (i, solve) in solves.iter().enumerate() {
+        let position = replay.apply(solve);
+        replay.update_streaks(position, solve.star.ts);
+
+        // Stars awarded in the same second are a single event
+        if solves
+            .get(i + 1)
+            .is_some_and(|next| next.star.ts == solve.star.ts)
+        {
+            continue;
+        }
+
+        for (position, place) in replay.placed(|_| true) {
+            let score = replay.scores[position].score;
+            let points = &mut history[position].points;
+            if points
+                .last()
+                .is_none_or(|p| (p.place, p.score) != (place, score))
+            {
+                points.push(HistoryPoint {
+                    ts: solve.star.ts,
+                    place,
+                    score,
+                });
+            }
+        }
+    }
+
+    let order: HashMap<_, _> = replay
+        .placed(|_| true)
+        .into_iter()
+        .enumerate()
+        .map(|(i, (position, _))| (position, i))
+        .collect();
+    let mut history: Vec<_> = history.into_iter().enumerate().collect();
+    history.sort_by_key(|(position, _)| order[position]);
+    history.into_iter().map(|(_, h)| h).collect()
+}
+
+/// Combine the scoreboards of a leaderboard from several years into all-time standings. Scores are
+/// added as they are, so every year counts according to its own scoring rule.
+pub fn all_time(scoreboards: &[Scoreboard]) -> Vec<AllTimeScore> {
+    let mut scoreboards: Vec<_> = scoreboards.iter().collect();
+    scoreboards.sort_by_key(|s| s.year);
+
+    let mut members: HashMap<usize, AllTimeScore> = HashMap::new();
+    for scoreboard in scoreboards {
+        for m in scoreboard.scores.iter().filter(|m| m.num_stars > 0) {
+            let entry = members.entry(m.member.id).or_insert_with(|| AllTimeScore {
+                member: m.member.clone(),
+                place: 0,
+                score: 0,
+                num_stars: 0,
+                years: Vec::new(),
+                best_finish: None,
+            });
+
+            // Use the most recent name, in case the member has changed it
+            entry.member.name = m.member.name.clone();
+            entry.score += m.score;
+            entry.num_stars += m.num_stars;
+            entry.years.push(scoreboard.year);
+            if entry.best_finish.is_none_or(|(place, _)| m.place <= place) {
+                entry.best_finish = Some((m.place, scoreboard.year));
+            }
+        }
+    }
+
+    let compare = |a: &AllTimeScore, b: &AllTimeScore| {
+        b.score.cmp(&a.score).then(b.num_stars.cmp(&a.num_stars))
+    };
+    let mut scores: Vec<_> = members.into_values().collect();
+    scores.sort_by(|a, b| compare(a, b).then(a.member.id.cmp(&b.member.id)));
+    for i in 0..scores.len() {
+        scores[i].place = if i > 0 && compare(&scores[i - 1], &scores[i]).is_eq() {
+            scores[i - 1].place
+        } else {
+            i + 1
+        };
+    }
+    scores
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    const FIXTURE: &str = include_str!("../fixtures/2022-273465.json");
+
+    fn leaderboard() -> Leaderboard {
+        serde_json::from_str(FIXTURE).unwrap()
+    }
+
+    fn config(extra: &str) -> LeaderboardConfig {
+        let base = "id = 273465\nname = \"Test\"\nslug = \"test\"\ncode = \"\"\nyear = 2022\n";
+        toml::from_str(&format!("{}{}", base, extra)).unwrap()
+    }
+
+    fn metadata(toml_str: &str) -> HashMap<usize, MemberMetadata> {
+        let raw: HashMap<String, MemberMetadata> = toml::from_str(toml_str).unwrap();
+        raw.into_iter()
+            .map(|(id, m)| (id.parse().unwrap(), m))
+            .collect()
+    }
+
+    fn at(day: u32, hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2022, 12, day, hour, 0, 0).unwrap()
+    }
+
+    fn score(scoreboard: &Scoreboard, member_id: usize) -> &MemberScore {
+        scoreboard
+            .scores
+            .iter()
+            .find(|m| m.member.id == member_id)
+            .unwrap()
+    }
+
+    #[test]
+    fn streaks_skip_voided_days() {
+        // Puzzles are released at 05:00 UTC
+        let completed = [Some(at(1, 6)), None, Some(at(3, 6)), None];
+        let now = at(4, 12);
+
+        assert_eq!(streaks(&completed, &config(""), None, now), (1, 1));
+        let voided = config("voided_days = [2]");
+        assert_eq!(streaks(&completed, &voided, None, now), (2, 2));
+
+        // Once day 4 has been out for 24 hours the current streak is broken
+        assert_eq!(streaks(&completed, &voided, None, at(5, 6)), (0, 2));
+    }
+
+    #[test]
+    fn replay_as_of_only_counts_earlier_stars() {
+        let cfg = config("");
+        let view = View {
+            as_of: Some(at(2, 6)),
+            show_all: true,
+        };
+        let scoreboard = Scoreboard::with_view(&leaderboard(), &cfg, &HashMap::new(), view);
+        assert_eq!(score(&scoreboard, 273465).score, 200);
+        assert_eq!(score(&scoreboard, 1001).score, 200);
+        assert_eq!(score(&scoreboard, 273465).place, 1);
+        assert_eq!(score(&scoreboard, 1001).place, 1);
+
+        let scoreboard = Scoreboard::from_leaderboard(&leaderboard(), &cfg, &HashMap::new());
+        assert_eq!(score(&scoreboard, 273465).score, 350);
+        assert_eq!(score(&scoreboard, 1001).score, 290);
+        assert_eq!(score(&scoreboard, 1001).place, 2);
+    }
+
+    #[test]
+    fn merging_aliases_keeps_the_earliest_star() {
+        let leaderboard = leaderboard();
+        let merged = merge_aliases(&leaderboard, &metadata("1001 = { aliases = [273465] }"));
+        assert!(!merged.members.contains_key(&273465));
+        assert_eq!(merged.owner_id, Some(1001));
+
+        let member = &merged.members[&1001];
+        let days = &member.completion_day_level;
+        let andreas = &leaderboard.members[&273465].completion_day_level;
+        let jane = &leaderboard.members[&1001].completion_day_level;
+        assert_eq!(days.len(), 5);
+        assert_eq!(days[&1].part1.ts, jane[&1].part1.ts);
+        assert_eq!(days[&3].part2.unwrap().ts, andreas[&3].part2.unwrap().ts);
+        assert_eq!(days[&5].part1.ts, andreas[&5].part1.ts);
+        assert!(days[&5].part2.is_none());
+        assert_eq!(member.stars, 9);
+    }
+
+    #[test]
+    fn alias_takes_the_place_of_a_missing_main_account() {
+        let leaderboard = leaderboard();
+        let merged = merge_aliases(&leaderboard, &metadata("5000 = { aliases = [1001] }"));
+        assert!(!merged.members.contains_key(&1001));
+        assert_eq!(merged.members[&5000].id, 5000);
+        assert_eq!(merged.members[&5000].name.as_deref(), Some("Jane Doe"));
+    }
+
+    /// Return the fixture with copies of members under new IDs
+    fn with_copies(copies: &[(usize, usize)]) -> Leaderboard {
+        let mut leaderboard = leaderboard();
+        for &(id, original) in copies {
+            let member = parser::Member {
+                id,
+                ..leaderboard.members[&original].clone()
+            };
+            leaderboard.members.insert(id, member);
+        }
+        leaderboard
+    }
+
+    fn contention(scoreboard: &Scoreboard, member_id: usize) -> Contention {
+        score(scoreboard, member_id).outlook.unwrap().contention
+    }
+
+    #[test]
+    fn contention_during_the_event() {
+        // Only day 25 is still worth anything
+        let cfg = config("[scoring_curve]\ntable = [50, 0]");
+        let view = View {
+            as_of: Some(at(25, 12)),
+            show_all: false,
+        };
+        let scoreboard = Scoreboard::with_view(&leaderboard(), &cfg, &HashMap::new(), view);
+        assert_eq!(score(&scoreboard, 273465).outlook.unwrap().best_score, 450);
+        assert_eq!(score(&scoreboard, 1001).outlook.unwrap().best_score, 300);
+        assert_eq!(contention(&scoreboard, 273465), Contention::Clinched);
+        assert_eq!(contention(&scoreboard, 1001), Contention::CanFinishTop3);
+
+        let view = View {
+            as_of: Some(at(27, 0)),
+            ..view
+        };
+        let scoreboard = Scoreboard::with_view(&leaderboard(), &cfg, &HashMap::new(), view);
+        assert!(scoreboard.scores.iter().all(|m| m.outlook.is_none()));
+    }
+
+    #[test]
+    fn hidden_members_count_towards_contention() {
+        let cfg = config("[scoring_curve]\ntable = [50, 0]");
+        let view = View {
+            as_of: Some(at(25, 12)),
+            show_all: false,
+        };
+
+        // A hidden member that can still catch up means first place isn't clinched
+        let leaderboard = with_copies(&[(2000, 273465)]);
+        let hidden = metadata("2000 = { hidden = true }");
+        let scoreboard = Scoreboard::with_view(&leaderboard, &cfg, &hidden, view);
+        assert_eq!(scoreboard.num_hidden, 1);
+        assert_eq!(contention(&scoreboard, 273465), Contention::CanWin);
+
+        // Members that are out of reach push others out of the top 3, even if they're hidden
+        let leaderboard = with_copies(&[(2001, 1001), (2002, 1001)]);
+        let hidden = metadata("2001 = { hidden = true }\n2002 = { hidden = true }");
+        let scoreboard = Scoreboard::with_view(&leaderboard, &cfg, &hidden, view);
+        assert_eq!(scoreboard.scores.len(), 3);
+        assert_eq!(contention(&scoreboard, 1001), Contention::CanFinishTop3);
+        assert_eq!(contention(&scoreboard, 1002), Contention::Eliminated);
+    }
 
-        // Sort
-        Self { year, scores }
+    #[test]
+    fn hidden_members_count_towards_their_team() {
+        let cfg = config("min_stars = 5\n[[teams]]\nname = \"A\"\nmembers = [273465, 1002]");
+        let view = View {
+            as_of: Some(at(3, 6)),
+            show_all: false,
+        };
+        let scoreboard = Scoreboard::with_view(&leaderboard(), &cfg, &HashMap::new(), view);
+        assert_eq!(scoreboard.num_hidden, 3);
+        assert_eq!(scoreboard.teams[0].score, 200);
+        let members: Vec<_> = scoreboard.teams[0].members.iter().map(|m| m.id).collect();
+        assert_eq!(members, [273465, 1002]);
+        assert_eq!(scoreboard.member_name(1002), Some("(anonymous user #1002)"));
     }
 }
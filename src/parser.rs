@@ -4,8 +4,7 @@ use serde::{de::Error as SerdeError, Deserialize};
 use serde_json::Value;
 use std::collections::HashMap;
 
-/// Parser for the Advent of Code leaderboard JSON. We ignore keys that are not interesting to this
-/// leaderboard calculation.
+/// Parser for the Advent of Code leaderboard JSON.
 ///
 /// Example:
 ///
@@ -18,8 +17,8 @@ use std::collections::HashMap;
 ///             "last_star_ts": 1608891747,
 ///             "completion_day_level": {
 ///                 "4": {
-///                     "1": {"get_star_ts": 1607071035},
-///                     "2": {"get_star_ts": 1607072594}
+///                     "1": {"get_star_ts": 1607071035, "star_index": 1069},
+///                     "2": {"get_star_ts": 1607072594, "star_index": 3425}
 ///                 }
 ///             },
 ///             "name": "Andreas Runfalk",
@@ -37,30 +36,77 @@ use std::collections::HashMap;
 ///   from the from the dictionary.
 /// - If a user is marked as anonymous they'll not have a name key.
 /// - There is no way to get the associated GitHub of a user through the JSON.
+/// - `last_star_ts` is 0 for users that have no stars.
+/// - `star_index` is missing from older leaderboards.
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Star {
+    #[serde(rename = "get_star_ts", deserialize_with = "parse_json_ts")]
+    pub ts: DateTime<Utc>,
+
+    /// Increasing counter across all of Advent of Code, which gives the exact order of stars that
+    /// were awarded within the same second
+    #[serde(rename = "star_index", default)]
+    pub index: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct Day {
-    #[serde(rename = "1", deserialize_with = "parse_day_progress")]
-    pub part1: DateTime<Utc>,
-    #[serde(rename = "2", deserialize_with = "parse_day_progress_opt", default)]
-    pub part2: Option<DateTime<Utc>>,
+    #[serde(rename = "1")]
+    pub part1: Star,
+    #[serde(rename = "2", default)]
+    pub part2: Option<Star>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Member {
     #[serde(deserialize_with = "parse_json_number")]
     pub id: usize,
     pub name: Option<String>,
     pub completion_day_level: HashMap<usize, Day>,
+    #[serde(default)]
+    pub stars: usize,
+    #[serde(default)]
+    pub local_score: usize,
+    #[serde(default)]
+    pub global_score: usize,
+    #[serde(deserialize_with = "parse_json_ts_opt", default)]
+    pub last_star_ts: Option<DateTime<Utc>>,
+
+    /// IDs of the leaderboards the member is on, only set for combined leaderboards
+    #[serde(skip)]
+    pub boards: Vec<usize>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Leaderboard {
     #[serde(deserialize_with = "parse_json_number")]
     pub event: i32,
+    #[serde(deserialize_with = "parse_json_number_opt", default)]
+    pub owner_id: Option<usize>,
     pub members: HashMap<usize, Member>,
 }
 
+impl Leaderboard {
+    /// Add the members of another leaderboard from the same year. Members are the same across
+    /// leaderboards, except for the local score, so members that are on both keep their data from
+    /// this one. Members are tagged with the ID of every leaderboard they're on.
+    pub fn combine(&mut self, id: usize, other: Leaderboard, other_id: usize) {
+        for member in self.members.values_mut() {
+            if member.boards.is_empty() {
+                member.boards.push(id);
+            }
+        }
+        for (member_id, member) in other.members {
+            self.members
+                .entry(member_id)
+                .or_insert(member)
+                .boards
+                .push(other_id);
+        }
+    }
+}
+
 fn parse_json_number<'de, D, T, E>(de: D) -> Result<T, D::Error>
 where
     D: serde::Deserializer<'de>,
@@ -75,6 +121,15 @@ where
     }
 }
 
+fn parse_json_number_opt<'de, D, T, E>(de: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: std::str::FromStr<Err = E>,
+    E: std::fmt::Display,
+{
+    Some(parse_json_number(de)).transpose()
+}
+
 fn parse_json_value_ts<E: serde::de::Error>(value: &Value) -> Result<DateTime<Utc>, E> {
     match value {
         Value::Number(n) => Ok(Utc
@@ -89,25 +144,20 @@ fn parse_json_value_ts<E: serde::de::Error>(value: &Value) -> Result<DateTime<Ut
     }
 }
 
-fn parse_day_progress<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
+fn parse_json_ts<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
 where
     D: serde::Deserializer<'de>,
     D::Error: serde::de::Error,
 {
-    if let Value::Object(m) = Value::deserialize(deserializer)? {
-        let ts_value = m
-            .get("get_star_ts")
-            .ok_or_else(|| D::Error::custom("invalid day progress"))?;
-        parse_json_value_ts(ts_value)
-    } else {
-        Err(D::Error::custom("invalid day progress"))
-    }
+    parse_json_value_ts(&Value::deserialize(deserializer)?)
 }
 
-fn parse_day_progress_opt<'de, D>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error>
+/// Parse a timestamp where 0 means that there is no timestamp
+fn parse_json_ts_opt<'de, D>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error>
 where
     D: serde::Deserializer<'de>,
     D::Error: serde::de::Error,
 {
-    Some(parse_day_progress(deserializer)).transpose()
+    let ts = parse_json_ts(deserializer)?;
+    Ok(Some(ts).filter(|ts| ts.timestamp() != 0))
 }
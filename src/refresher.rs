@@ -0,0 +1,116 @@
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+use crate::api;
+use crate::config::LeaderboardConfig;
+use crate::parser::Leaderboard;
+
+/// How often we try to refresh leaderboards. Advent of Code asks us to not fetch more often than
+/// once every 15 minutes, and the client caches responses for that long. The extra margin makes
+/// sure the cache has expired when we wake up, otherwise we'd only refresh every 30 minutes.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(15 * 60 + 30);
+
+/// The last leaderboard we managed to fetch
+#[derive(Debug, Clone)]
+pub struct CachedLeaderboard {
+    pub leaderboard: Arc<Leaderboard>,
+
+    /// When the leaderboard was fetched from Advent of Code
+    pub updated_at: DateTime<Utc>,
+
+    /// Error from the latest refresh attempt if it failed, which means the leaderboard may be
+    /// outdated
+    pub error: Option<Arc<anyhow::Error>>,
+}
+
+#[derive(Debug, Default)]
+struct Entry {
+    cached: Option<CachedLeaderboard>,
+
+    /// Error from the latest refresh attempt, if it failed
+    error: Option<Arc<anyhow::Error>>,
+}
+
+/// Leaderboards that are kept up to date in the background, keyed by slug
+#[derive(Debug, Clone, Default)]
+pub struct Leaderboards(Arc<RwLock<HashMap<String, Entry>>>);
+
+impl Leaderboards {
+    /// Return the last good leaderboard. If we've never managed to fetch it we return the error
+    /// from the latest attempt, or `None` if the first attempt hasn't finished yet.
+    pub async fn get(&self, slug: &str) -> Result<CachedLeaderboard, Option<Arc<anyhow::Error>>> {
+        match self.0.read().await.get(slug) {
+            Some(Entry {
+                cached: Some(cached),
+                ..
+            }) => Ok(cached.clone()),
+            Some(Entry { error, .. }) => Err(error.clone()),
+            None => Err(None),
+        }
+    }
+
+    async fn refresh(&self, client: &api::Client, cfg: &LeaderboardConfig) {
+        let (year, id) = (cfg.year, cfg.id);
+        let result = client.fetch_leaderboard(cfg).await;
+
+        let mut state = self.0.write().await;
+        let entry = state.entry(cfg.slug.clone()).or_default();
+        match result {
+            Ok((snapshot, error)) => {
+                if let Some(e) = &error {
+                    tracing::error!(
+                        "Unable to refresh leaderboard {} ({}), using snapshot from {}: {:#}",
+                        id,
+                        year,
+                        snapshot.fetched_at,
+                        e
+                    );
+                }
+                let error = error.map(Arc::new);
+                entry.cached = Some(CachedLeaderboard {
+                    leaderboard: Arc::new(snapshot.leaderboard),
+                    updated_at: snapshot.fetched_at,
+                    error: error.clone(),
+                });
+                entry.error = error;
+            }
+            Err(e) => {
+                tracing::error!("Unable to refresh leaderboard {} ({}): {:#}", id, year, e);
+                let error = Arc::new(e);
+                if let Some(cached) = entry.cached.as_mut() {
+                    cached.error = Some(error.clone());
+                }
+                entry.error = Some(error);
+            }
+        }
+    }
+}
+
+/// Start a background task that refreshes the given leaderboards on a regular interval. Requests
+/// are served from the returned state so visitors never have to wait for Advent of Code.
+pub fn spawn(
+    client: Arc<api::Client>,
+    leaderboards: impl IntoIterator<Item = LeaderboardConfig>,
+) -> Leaderboards {
+    let state = Leaderboards::default();
+
+    // Every leaderboard gets its own task, so a slow leaderboard doesn't delay the others. The
+    // client makes sure that leaderboards shown under several slugs are only fetched once.
+    for cfg in leaderboards {
+        let state = state.clone();
+        let client = client.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                state.refresh(&client, &cfg).await;
+            }
+        });
+    }
+
+    state
+}
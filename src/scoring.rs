@@ -0,0 +1,317 @@
+use chrono::{DateTime, Utc};
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::config::{LeaderboardConfig, ScoringCurve, ScoringRule, TeamAggregation, TieBreaker};
+use crate::model::MemberScore;
+
+/// Everything a scoring rule may want to know about a single star
+#[derive(Debug, Clone, Copy)]
+pub struct StarContext {
+    pub part: usize,
+    pub released: DateTime<Utc>,
+    pub completed: DateTime<Utc>,
+
+    /// How many members on the leaderboard got this star before this member
+    pub rank: usize,
+    pub num_members: usize,
+}
+
+/// A way of awarding points for stars and placing members based on them
+pub trait Scoring: fmt::Debug + Send + Sync {
+    /// Short name of the scoring rule
+    fn name(&self) -> &'static str;
+
+    /// Explanation of how points are awarded, suitable for showing to members
+    fn description(&self) -> String;
+
+    /// Return the points awarded for a single star
+    fn score_star(&self, star: &StarContext) -> usize;
+
+    /// Return the best score a star can get if it's awarded at the completion time of the context
+    /// or later. Unless the rule says otherwise, that is when it's awarded as early as possible.
+    fn best_star_score(&self, star: &StarContext) -> usize {
+        self.score_star(star)
+    }
+
+    /// Order two members so the one that should be placed higher comes first. Members that
+    /// compare equal are tied.
+    fn compare(&self, a: &MemberScore, b: &MemberScore) -> Ordering {
+        b.score.cmp(&a.score)
+    }
+
+    /// Format a score for display
+    fn format_score(&self, score: usize) -> String {
+        score.to_string()
+    }
+}
+
+/// Rewards solving puzzles within a day of release, which values consistency over speed
+#[derive(Debug)]
+pub struct Consistency(pub ScoringCurve);
+
+impl Scoring for Consistency {
+    fn name(&self) -> &'static str {
+        "consistency"
+    }
+
+    fn description(&self) -> String {
+        let curve = &self.0;
+        let mut description = if let Some(table) = &curve.table {
+            let mut description = format!(
+                "If you solve the puzzle within 24 hours from when it's released you get {} points.",
+                table[0]
+            );
+            for (days_late, points) in table.iter().enumerate().skip(1) {
+                let unit = if days_late == 1 { "day" } else { "days" };
+                description += &format!(" {} {} late it's worth {}.", days_late, unit, points);
+            }
+            if table.len() > 1 {
+                description += " Solving it even later gives the same points as the last day.";
+            }
+            description
+        } else {
+            format!(
+                "If you solve the puzzle within 24 hours from when it's released you get full \
+                 points ({}). For each day you lag behind the score decreases by {}, but you can \
+                 never get less than {} points for solving a puzzle, which is what you get when \
+                 you're more than {} days late.",
+                curve.base, curve.decay, curve.floor, curve.cutoff,
+            )
+        };
+
+        if curve.part1_weight != 1.0 || curve.part2_weight != 1.0 {
+            description += &format!(
+                " The points are multiplied by {} for the first star and {} for the second.",
+                curve.part1_weight, curve.part2_weight
+            );
+        }
+        description
+    }
+
+    fn score_star(&self, star: &StarContext) -> usize {
+        let days_late = (star.completed - star.released).num_days().max(0) as usize;
+        self.weighted_points(star.part, days_late)
+    }
+
+    fn best_star_score(&self, star: &StarContext) -> usize {
+        let days_late = (star.completed - star.released).num_days().max(0) as usize;
+        match &self.0.table {
+            // A custom table doesn't have to decrease, so a star may be worth more if it's later.
+            // Every day past the end of the table is worth the same as the last entry.
+            Some(table) => (days_late.min(table.len() - 1)..table.len())
+                .map(|days_late| self.weighted_points(star.part, days_late))
+                .max()
+                .unwrap(),
+
+            // The curve never increases with lateness, so getting the star now is the best case
+            None => self.weighted_points(star.part, days_late),
+        }
+    }
+}
+
+impl Consistency {
+    fn weighted_points(&self, part: usize, days_late: usize) -> usize {
+        let weight = match part {
+            1 => self.0.part1_weight,
+            _ => self.0.part2_weight,
+        };
+        (self.0.points(days_late) as f64 * weight).round() as usize
+    }
+}
+
+/// The same scoring that the official private leaderboard uses
+#[derive(Debug)]
+pub struct LocalScore;
+
+impl Scoring for LocalScore {
+    fn name(&self) -> &'static str {
+        "local score"
+    }
+
+    fn description(&self) -> String {
+        "The same as the official leaderboard. The first member to get a star gets as many \
+         points as there are members on the leaderboard, the second gets one point less and so \
+         on."
+        .to_owned()
+    }
+
+    fn score_star(&self, star: &StarContext) -> usize {
+        star.num_members - star.rank
+    }
+}
+
+/// Every star is worth the same, no matter when it was solved
+#[derive(Debug)]
+pub struct StarsOnly;
+
+impl Scoring for StarsOnly {
+    fn name(&self) -> &'static str {
+        "stars only"
+    }
+
+    fn description(&self) -> String {
+        "Every star is worth one point, no matter when you get it.".to_owned()
+    }
+
+    fn score_star(&self, _star: &StarContext) -> usize {
+        1
+    }
+}
+
+/// Members with the most stars win, and ties are broken by the total time it took to solve the
+/// puzzles since they were released. The score is the total time in seconds.
+#[derive(Debug)]
+pub struct SolveTime;
+
+impl Scoring for SolveTime {
+    fn name(&self) -> &'static str {
+        "solve time"
+    }
+
+    fn description(&self) -> String {
+        "The member with the most stars wins. Members with the same number of stars are ranked by \
+         the total time from release until they got each star, lowest first."
+            .to_owned()
+    }
+
+    fn score_star(&self, star: &StarContext) -> usize {
+        (star.completed - star.released).num_seconds().max(0) as usize
+    }
+
+    fn compare(&self, a: &MemberScore, b: &MemberScore) -> Ordering {
+        b.num_solved.cmp(&a.num_solved).then(a.score.cmp(&b.score))
+    }
+
+    fn format_score(&self, score: usize) -> String {
+        format!("{}h", score / 3600)
+    }
+}
+
+impl TieBreaker {
+    /// Order two members with the same score so the one that should be placed higher comes
+    /// first. Members that compare equal share the same place.
+    pub fn compare(&self, a: &MemberScore, b: &MemberScore) -> Ordering {
+        match self {
+            Self::Shared => Ordering::Equal,
+            Self::MostStars => b.num_solved.cmp(&a.num_solved),
+            Self::EarliestLastStar => match (a.last_star, b.last_star) {
+                (Some(a), Some(b)) => (a.ts, a.index).cmp(&(b.ts, b.index)),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            },
+            Self::SolveTime => a.solve_time.cmp(&b.solve_time),
+            Self::LongestStreak => b.longest_streak.cmp(&a.longest_streak),
+        }
+    }
+
+    /// Explanation of how ties are broken, suitable for showing to members
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::Shared => "Members with the same score share the same place.",
+            Self::MostStars => "Members with the same score are ranked by who has the most stars.",
+            Self::EarliestLastStar => {
+                "Members with the same score are ranked by who got their last star first."
+            }
+            Self::SolveTime => {
+                "Members with the same score are ranked by the lowest total time from release \
+                 until they got each star."
+            }
+            Self::LongestStreak => {
+                "Members with the same score are ranked by who has the longest streak of days \
+                 solved within 24 hours."
+            }
+        }
+    }
+}
+
+impl TeamAggregation {
+    /// Return the score of a team with the given members
+    pub fn aggregate(&self, members: &[&MemberScore]) -> usize {
+        match self {
+            Self::Sum => members.iter().map(|m| m.score).sum(),
+            Self::AverageTop(n) => {
+                let mut scores: Vec<_> = members.iter().map(|m| m.score).collect();
+                scores.sort_unstable_by(|a, b| b.cmp(a));
+                scores.truncate(*n);
+                if scores.is_empty() {
+                    0
+                } else {
+                    (scores.iter().sum::<usize>() as f64 / scores.len() as f64).round() as usize
+                }
+            }
+            Self::BestPerDay => {
+                let days = members
+                    .iter()
+                    .map(|m| m.day_scores.len())
+                    .max()
+                    .unwrap_or(0);
+                (0..days)
+                    .map(|day| members.iter().map(|m| m.day_scores[day]).max().unwrap_or(0))
+                    .sum()
+            }
+        }
+    }
+
+    /// Explanation of how team scores are calculated, suitable for showing to members
+    pub fn description(&self) -> String {
+        match self {
+            Self::Sum => "A team's score is the sum of its members' scores.".to_owned(),
+            Self::AverageTop(n) => format!(
+                "A team's score is the average of the top {} scores among its members.",
+                n
+            ),
+            Self::BestPerDay => "A team gets the points of its best member for each day. Bonuses \
+                                 don't count towards the team score."
+                .to_owned(),
+        }
+    }
+}
+
+/// Explanation of how points are awarded on the given leaderboard, including bonuses that apply
+/// regardless of the scoring rule
+pub fn description(cfg: &LeaderboardConfig, scoring: &dyn Scoring) -> String {
+    let mut description = scoring.description();
+    if cfg.streak_bonus > 0 {
+        description += &format!(
+            " You also get {} extra points for each day in your longest streak of puzzles where \
+             you got both stars within 24 hours from release.",
+            cfg.streak_bonus
+        );
+    }
+
+    if let Some(hour) = cfg.local_release_hour {
+        description += &format!(
+            " Members with a configured timezone are scored as if the puzzle was released at \
+             {:02}:00 their time.",
+            hour
+        );
+    }
+
+    let mut voided_days = cfg.voided_days.clone();
+    voided_days.sort_unstable();
+    for day in voided_days {
+        description += &format!(" Day {} doesn't count towards the score.", day);
+    }
+    let mut multipliers: Vec<_> = cfg
+        .day_multipliers
+        .iter()
+        .filter(|(&day, _)| !cfg.is_voided(day))
+        .collect();
+    multipliers.sort_by_key(|(&day, _)| day);
+    for (day, multiplier) in multipliers {
+        description += &format!(" Points for day {} are multiplied by {}.", day, multiplier);
+    }
+    description
+}
+
+/// Return the scoring rule for the given leaderboard
+pub fn from_config(cfg: &LeaderboardConfig) -> Box<dyn Scoring> {
+    match cfg.scoring {
+        ScoringRule::Consistency => Box::new(Consistency(cfg.scoring_curve.clone())),
+        ScoringRule::LocalScore => Box::new(LocalScore),
+        ScoringRule::StarsOnly => Box::new(StarsOnly),
+        ScoringRule::SolveTime => Box::new(SolveTime),
+    }
+}
@@ -0,0 +1,85 @@
+use chrono::Duration;
+use std::collections::HashMap;
+
+use crate::model::Scoreboard;
+use crate::utils::release_time;
+
+/// Statistics for one part of a day
+#[derive(Debug, Default)]
+pub struct PartStats {
+    /// Number of members that got this star
+    pub solvers: usize,
+
+    /// ID of the member that got this star first
+    pub first_solver: Option<usize>,
+
+    /// Time from release until the star was awarded
+    pub fastest: Option<Duration>,
+    pub median: Option<Duration>,
+}
+
+#[derive(Debug)]
+pub struct DayStats {
+    pub day: usize,
+    pub part1: PartStats,
+    pub part2: PartStats,
+
+    /// Time between getting the first and the second star, for members that got both
+    pub fastest_gap: Option<Duration>,
+    pub median_gap: Option<Duration>,
+}
+
+/// Return the median of the given durations
+fn median(mut durations: Vec<Duration>) -> Option<Duration> {
+    durations.sort();
+    let mid = durations.len() / 2;
+    match durations.len() {
+        0 => None,
+        n if n % 2 == 0 => Some((durations[mid - 1] + durations[mid]) / 2),
+        _ => Some(durations[mid]),
+    }
+}
+
+fn part_stats(delays: Vec<(usize, Duration)>) -> PartStats {
+    PartStats {
+        solvers: delays.len(),
+        first_solver: delays.first().map(|&(member_id, _)| member_id),
+        fastest: delays.iter().map(|&(_, d)| d).min(),
+        median: median(delays.into_iter().map(|(_, d)| d).collect()),
+    }
+}
+
+/// Compute statistics for every day of the scoreboard
+pub fn day_stats(scoreboard: &Scoreboard) -> Vec<DayStats> {
+    (1..=scoreboard.days)
+        .map(|day| {
+            let released = release_time(scoreboard.year, day as u32).unwrap();
+
+            // Solves are in the order they were awarded, so the first one is the first solver
+            let mut part1 = Vec::new();
+            let mut part2 = Vec::new();
+            let mut part1_times = HashMap::new();
+            let mut gaps = Vec::new();
+            for solve in scoreboard.solves.iter().filter(|s| s.day == day) {
+                let delay = solve.star.ts - released;
+                if solve.part == 1 {
+                    part1.push((solve.member_id, delay));
+                    part1_times.insert(solve.member_id, solve.star.ts);
+                } else {
+                    part2.push((solve.member_id, delay));
+                    if let Some(part1_ts) = part1_times.get(&solve.member_id) {
+                        gaps.push(solve.star.ts - *part1_ts);
+                    }
+                }
+            }
+
+            DayStats {
+                day,
+                part1: part_stats(part1),
+                part2: part_stats(part2),
+                fastest_gap: gaps.iter().min().copied(),
+                median_gap: median(gaps),
+            }
+        })
+        .collect()
+}
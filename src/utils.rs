@@ -2,10 +2,22 @@ use anyhow::{anyhow, Result};
 use chrono::{DateTime, Duration, TimeZone, Utc};
 use chrono_tz::EST;
 
+/// The most puzzles an event can have
+pub const MAX_DAYS: u32 = 25;
+
+/// Return the number of puzzles in the given year. Since 2025 there are only 12 puzzles.
+pub fn num_days(year: i32) -> u32 {
+    if year >= 2025 {
+        12
+    } else {
+        25
+    }
+}
+
 /// Return the release time of the puzzle for the given year and day
 pub fn release_time(year: i32, day: u32) -> Result<DateTime<Utc>> {
-    if day == 0 || day > 25 {
-        return Err(anyhow!("Day must be between 1 and 25"));
+    if day == 0 || day > MAX_DAYS {
+        return Err(anyhow!("Day must be between 1 and {}", MAX_DAYS));
     }
     // Unwrap is OK here since we know that no time change should happen in december
     Ok(EST
@@ -15,12 +27,112 @@ pub fn release_time(year: i32, day: u32) -> Result<DateTime<Utc>> {
         .with_timezone(&Utc))
 }
 
-/// Calculate the score for a puzzle based on the duration from release
-pub fn score_puzzle(completion_time: Duration) -> usize {
-    let num_days = completion_time.num_days() as usize;
-    if num_days > 8 {
-        10
+/// Return the first time at or after the given time when a clock in the given timezone shows the
+/// start of the given hour
+pub fn next_local_hour<Tz: TimeZone>(after: DateTime<Utc>, tz: &Tz, hour: u32) -> DateTime<Utc> {
+    let date = after.with_timezone(tz).date_naive();
+    [Some(date), date.succ_opt()]
+        .into_iter()
+        .flatten()
+        .filter_map(|d| {
+            tz.from_local_datetime(&d.and_hms_opt(hour, 0, 0)?)
+                .earliest()
+        })
+        .map(|t| t.with_timezone(&Utc))
+        .find(|t| *t >= after)
+        // The hour can only be missing on both days if it's skipped by daylight saving time
+        .unwrap_or(after)
+}
+
+/// Return a list of years for display, like "2020, 2021 and 2022"
+pub fn join_years(years: &[i32]) -> String {
+    match years {
+        [] => String::new(),
+        [year] => year.to_string(),
+        [rest @ .., last] => format!(
+            "{} and {}",
+            rest.iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", "),
+            last
+        ),
+    }
+}
+
+/// Wrap text at word boundaries so no line is longer than the given width, unless a single word
+/// is longer than that
+pub fn wrap(text: &str, width: usize) -> String {
+    let mut lines = vec![String::new()];
+    for word in text.split_whitespace() {
+        let line = lines.last_mut().unwrap();
+        if !line.is_empty() && line.len() + 1 + word.len() > width {
+            lines.push(word.to_owned());
+        } else {
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+        }
+    }
+    lines.join("\n")
+}
+
+/// Format a duration as hours, minutes and seconds, prefixed by the number of days if it's longer
+/// than a day
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.num_seconds().max(0);
+    let (days, seconds) = (seconds / 86400, seconds % 86400);
+    let time = format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    );
+    if days > 0 {
+        format!("{}d {}", days, time)
     } else {
-        50 - 5 * num_days
+        time
+    }
+}
+
+/// Format a duration like `format_duration`, or as a dash if there isn't one
+pub fn format_optional_duration(duration: Option<Duration>) -> String {
+    duration
+        .map(format_duration)
+        .unwrap_or_else(|| "-".to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::FixedOffset;
+    use chrono_tz::America::Los_Angeles;
+
+    fn utc(day: u32, hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2022, 12, day, hour, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn next_local_hour_east_of_utc() {
+        // The puzzle is released at 14:00 in UTC+9, so 06:00 is on the next day
+        let tz = FixedOffset::east_opt(9 * 3600).unwrap();
+        let released = release_time(2022, 1).unwrap();
+        assert_eq!(next_local_hour(released, &tz, 6), utc(1, 21));
+    }
+
+    #[test]
+    fn next_local_hour_west_of_utc() {
+        // The puzzle is released at 21:00 on the previous day in Los Angeles
+        let released = release_time(2022, 1).unwrap();
+        assert_eq!(next_local_hour(released, &Los_Angeles, 6), utc(1, 14));
+        assert_eq!(next_local_hour(released, &Los_Angeles, 22), utc(1, 6));
+    }
+
+    #[test]
+    fn next_local_hour_at_release() {
+        let released = release_time(2022, 1).unwrap();
+        assert_eq!(next_local_hour(released, &EST, 0), released);
+        assert_eq!(next_local_hour(released, &Utc, 5), released);
     }
 }